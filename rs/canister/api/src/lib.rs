@@ -1,8 +1,11 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use candid::CandidType;
 use rand_core::CryptoRngCore;
-use rsa::{Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use rsa::sha2::Sha256;
+use rsa::{Oaep, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
 
 mod lifecycle;
@@ -14,10 +17,12 @@ pub use queries::*;
 pub use updates::*;
 
 pub const ONE_MINUTE: Milliseconds = 60 * 1000;
-pub const ONE_DAY: Milliseconds = 24 * 60 * ONE_MINUTE;
+pub const ONE_HOUR: Milliseconds = 60 * ONE_MINUTE;
+pub const ONE_DAY: Milliseconds = 24 * ONE_HOUR;
 pub const NANOS_PER_MILLISECOND: u64 = 1_000_000;
 pub const DEFAULT_SESSION_EXPIRATION_PERIOD: Nanoseconds = 30 * ONE_DAY * NANOS_PER_MILLISECOND;
 pub const MAX_SESSION_EXPIRATION_PERIOD: Nanoseconds = 90 * ONE_DAY * NANOS_PER_MILLISECOND;
+pub const DEFAULT_MAX_INCORRECT_CODE_ATTEMPTS: u32 = 3;
 
 pub type Hash = [u8; 32];
 pub type Milliseconds = u64;
@@ -39,9 +44,147 @@ pub struct SignedDelegation {
     pub signature: Vec<u8>,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmailRateLimitPolicy {
+    // The number of emails which can be sent to an address before any backoff is applied
+    pub free_emails: u32,
+    // The backoff applied to the first email beyond `free_emails`, doubling for each subsequent email
+    pub initial_backoff: Milliseconds,
+    pub max_backoff: Milliseconds,
+    // Once this long has passed since the latest email was sent to an address, its backoff is reset
+    pub reset_after: Milliseconds,
+}
+
+impl Default for EmailRateLimitPolicy {
+    fn default() -> Self {
+        EmailRateLimitPolicy {
+            free_emails: 3,
+            initial_backoff: ONE_MINUTE,
+            max_backoff: ONE_HOUR,
+            reset_after: ONE_DAY,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ThrottlePolicy {
+    // Applied to each non-anonymous caller individually
+    pub per_caller: SlidingWindowLimit,
+    // Applied to all anonymous callers combined
+    pub anonymous: SlidingWindowLimit,
+    // When magic links are signed using a threshold key, each link costs the cycles attached to
+    // `sign_with_schnorr` (26_153_846_153 cycles when using ic-cdk), so this also bounds the rate
+    // at which cycles can be spent on signatures
+    pub global_emails_per_minute: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SlidingWindowLimit {
+    pub max_requests: u32,
+    pub window: Milliseconds,
+}
+
+impl Default for ThrottlePolicy {
+    fn default() -> Self {
+        ThrottlePolicy {
+            per_caller: SlidingWindowLimit {
+                max_requests: 20,
+                window: ONE_HOUR,
+            },
+            anonymous: SlidingWindowLimit {
+                max_requests: 10,
+                window: ONE_MINUTE,
+            },
+            global_emails_per_minute: 60,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MagicLinkExpiryPolicy {
+    pub default_time_to_live: Milliseconds,
+    // Callers may request a different time to live, which will be clamped to these bounds
+    pub min_time_to_live: Milliseconds,
+    pub max_time_to_live: Milliseconds,
+}
+
+impl MagicLinkExpiryPolicy {
+    pub fn time_to_live(&self, requested: Option<Milliseconds>) -> Milliseconds {
+        requested.map_or(self.default_time_to_live, |ttl| {
+            ttl.clamp(self.min_time_to_live, self.max_time_to_live)
+        })
+    }
+}
+
+impl Default for MagicLinkExpiryPolicy {
+    fn default() -> Self {
+        MagicLinkExpiryPolicy {
+            default_time_to_live: 10 * ONE_MINUTE,
+            min_time_to_live: ONE_MINUTE,
+            max_time_to_live: ONE_HOUR,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VerificationCodeFormat {
+    pub length: u8,
+    pub alphabet: VerificationCodeAlphabet,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VerificationCodeAlphabet {
+    Numeric,
+    CrockfordBase32,
+}
+
+impl Default for VerificationCodeFormat {
+    fn default() -> Self {
+        VerificationCodeFormat {
+            length: 3,
+            alphabet: VerificationCodeAlphabet::Numeric,
+        }
+    }
+}
+
+// The scheme used by the canister to sign magic links. Links signed using a previous scheme remain
+// valid until they expire.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub enum MagicLinkSignatureScheme {
+    #[default]
+    Rsa,
+    Ed25519,
+    // Signs via the IC's threshold Schnorr API so that the private key never exists in the canister
+    ThresholdEd25519 {
+        key_name: String,
+    },
+}
+
+// A key which the email sender may sign magic links with. Multiple keys can be accepted at once so
+// that the email sender's key can be rotated without invalidating links already sent.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmailSenderPublicKey {
+    // Either an RSA or an Ed25519 key
+    pub public_key_pem: String,
+    pub valid_from: Option<TimestampMillis>,
+    pub valid_until: Option<TimestampMillis>,
+}
+
+// Customizes the pages shown after a magic link is clicked
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Branding {
+    // Used in place of generic wording such as "the app" when set
+    pub app_name: Option<String>,
+    pub logo_url: Option<String>,
+    // If set, a link back to the app is shown on the success page
+    pub return_url: Option<String>,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub enum EmailSenderConfig {
     Aws(AwsEmailSenderConfig),
+    HttpRelay(HttpRelayEmailSenderConfig),
+    Failover(FailoverEmailSenderConfig),
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -52,9 +195,29 @@ pub struct AwsEmailSenderConfig {
     pub secret_key: String,
 }
 
+// Posts magic links to a relay of the operator's choosing, with each request signed using HMAC
+// Providers such as SendGrid or Postmark should be called from the relay rather than from the
+// canister, since they don't deduplicate the request which each replica makes
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HttpRelayEmailSenderConfig {
+    pub url: String,
+    pub hmac_key: String,
+}
+
+// Tries each sender in order until one succeeds. Senders which fail `max_consecutive_failures` times
+// in a row (default 3) are only tried after the others until `cooldown` (default 5 minutes) passes.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FailoverEmailSenderConfig {
+    pub senders: Vec<EmailSenderConfig>,
+    pub max_consecutive_failures: Option<u32>,
+    pub cooldown: Option<Milliseconds>,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub enum EncryptedEmailSenderConfig {
     Aws(EncryptedAwsEmailSenderConfig),
+    HttpRelay(EncryptedHttpRelayEmailSenderConfig),
+    Failover(EncryptedFailoverEmailSenderConfig),
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -65,9 +228,24 @@ pub struct EncryptedAwsEmailSenderConfig {
     pub secret_key_encrypted: String,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptedHttpRelayEmailSenderConfig {
+    pub url: String,
+    pub hmac_key_encrypted: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptedFailoverEmailSenderConfig {
+    pub senders: Vec<EncryptedEmailSenderConfig>,
+    pub max_consecutive_failures: Option<u32>,
+    pub cooldown: Option<Milliseconds>,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub enum EmailSenderConfigPublic {
     Aws(AwsEmailSenderConfigPublic),
+    HttpRelay(HttpRelayEmailSenderConfigPublic),
+    Failover(FailoverEmailSenderConfigPublic),
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -77,6 +255,18 @@ pub struct AwsEmailSenderConfigPublic {
     pub access_key: String,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HttpRelayEmailSenderConfigPublic {
+    pub url: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FailoverEmailSenderConfigPublic {
+    pub senders: Vec<EmailSenderConfigPublic>,
+    pub max_consecutive_failures: Option<u32>,
+    pub cooldown: Option<Milliseconds>,
+}
+
 impl EmailSenderConfig {
     pub fn encrypt<R: CryptoRngCore>(
         self,
@@ -87,15 +277,43 @@ impl EmailSenderConfig {
             EmailSenderConfig::Aws(aws) => {
                 EncryptedEmailSenderConfig::Aws(aws.encrypt(rsa_public_key, rng))
             }
+            EmailSenderConfig::HttpRelay(relay) => {
+                EncryptedEmailSenderConfig::HttpRelay(relay.encrypt(rsa_public_key, rng))
+            }
+            EmailSenderConfig::Failover(failover) => {
+                EncryptedEmailSenderConfig::Failover(EncryptedFailoverEmailSenderConfig {
+                    senders: failover
+                        .senders
+                        .into_iter()
+                        .map(|c| c.encrypt(rsa_public_key, rng))
+                        .collect(),
+                    max_consecutive_failures: failover.max_consecutive_failures,
+                    cooldown: failover.cooldown,
+                })
+            }
         }
     }
 }
 
 impl EncryptedEmailSenderConfig {
-    pub fn decrypt(self, rsa_private_key: &RsaPrivateKey) -> EmailSenderConfig {
+    pub fn decrypt(self, rsa_private_key: &RsaPrivateKey) -> Result<EmailSenderConfig, String> {
         match self {
             EncryptedEmailSenderConfig::Aws(aws) => {
-                EmailSenderConfig::Aws(aws.decrypt(rsa_private_key))
+                aws.decrypt(rsa_private_key).map(EmailSenderConfig::Aws)
+            }
+            EncryptedEmailSenderConfig::HttpRelay(relay) => relay
+                .decrypt(rsa_private_key)
+                .map(EmailSenderConfig::HttpRelay),
+            EncryptedEmailSenderConfig::Failover(failover) => {
+                Ok(EmailSenderConfig::Failover(FailoverEmailSenderConfig {
+                    senders: failover
+                        .senders
+                        .into_iter()
+                        .map(|c| c.decrypt(rsa_private_key))
+                        .collect::<Result<_, _>>()?,
+                    max_consecutive_failures: failover.max_consecutive_failures,
+                    cooldown: failover.cooldown,
+                }))
             }
         }
     }
@@ -117,20 +335,57 @@ impl AwsEmailSenderConfig {
 }
 
 impl EncryptedAwsEmailSenderConfig {
-    pub fn decrypt(self, rsa_private_key: &RsaPrivateKey) -> AwsEmailSenderConfig {
-        AwsEmailSenderConfig {
+    pub fn decrypt(self, rsa_private_key: &RsaPrivateKey) -> Result<AwsEmailSenderConfig, String> {
+        Ok(AwsEmailSenderConfig {
             region: self.region,
             function_url: self.function_url,
             access_key: self.access_key,
-            secret_key: decrypt(&self.secret_key_encrypted, rsa_private_key),
+            secret_key: decrypt(&self.secret_key_encrypted, rsa_private_key)?,
+        })
+    }
+}
+
+impl HttpRelayEmailSenderConfig {
+    pub fn encrypt<R: CryptoRngCore>(
+        self,
+        rsa_public_key: &RsaPublicKey,
+        rng: &mut R,
+    ) -> EncryptedHttpRelayEmailSenderConfig {
+        EncryptedHttpRelayEmailSenderConfig {
+            url: self.url,
+            hmac_key_encrypted: encrypt(&self.hmac_key, rsa_public_key, rng),
         }
     }
 }
 
+impl EncryptedHttpRelayEmailSenderConfig {
+    pub fn decrypt(
+        self,
+        rsa_private_key: &RsaPrivateKey,
+    ) -> Result<HttpRelayEmailSenderConfig, String> {
+        Ok(HttpRelayEmailSenderConfig {
+            url: self.url,
+            hmac_key: decrypt(&self.hmac_key_encrypted, rsa_private_key)?,
+        })
+    }
+}
+
 impl From<&EmailSenderConfig> for EmailSenderConfigPublic {
     fn from(value: &EmailSenderConfig) -> Self {
         match value {
             EmailSenderConfig::Aws(aws) => EmailSenderConfigPublic::Aws(aws.into()),
+            EmailSenderConfig::HttpRelay(relay) => {
+                EmailSenderConfigPublic::HttpRelay(HttpRelayEmailSenderConfigPublic {
+                    url: relay.url.clone(),
+                })
+            }
+            EmailSenderConfig::Failover(failover) => {
+                EmailSenderConfigPublic::Failover(FailoverEmailSenderConfigPublic {
+                    senders: failover.senders.iter().map(|c| c.into()).collect(),
+                    max_consecutive_failures: failover.max_consecutive_failures,
+                    cooldown: failover.cooldown,
+                })
+            }
         }
     }
 }
@@ -145,19 +400,114 @@ impl From<&AwsEmailSenderConfig> for AwsEmailSenderConfigPublic {
     }
 }
 
+// Values are encrypted using a random AES-256-GCM key which is itself encrypted using RSA-OAEP, so
+// values of any length can be encrypted. The envelope is "v1:" followed by the base64 encoding of
+// the encrypted key, the nonce and the ciphertext.
+const ENVELOPE_V1_PREFIX: &str = "v1:";
+const NONCE_LENGTH: usize = 12;
+
 fn encrypt<R: CryptoRngCore>(value: &str, rsa_public_key: &RsaPublicKey, rng: &mut R) -> String {
-    BASE64_STANDARD.encode(
-        rsa_public_key
-            .encrypt(rng, Pkcs1v15Encrypt, value.as_bytes())
-            .unwrap(),
+    let mut aes_key = [0; 32];
+    let mut nonce = [0; NONCE_LENGTH];
+    rng.fill_bytes(&mut aes_key);
+    rng.fill_bytes(&mut nonce);
+
+    let ciphertext = Aes256Gcm::new(&aes_key.into())
+        .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+        .unwrap();
+    let encrypted_key = rsa_public_key
+        .encrypt(rng, Oaep::new::<Sha256>(), &aes_key)
+        .unwrap();
+
+    format!(
+        "{ENVELOPE_V1_PREFIX}{}",
+        BASE64_STANDARD.encode([encrypted_key, nonce.to_vec(), ciphertext].concat())
     )
 }
 
-fn decrypt(value: &str, rsa_private_key: &RsaPrivateKey) -> String {
-    String::from_utf8(
-        rsa_private_key
-            .decrypt(Pkcs1v15Encrypt, &BASE64_STANDARD.decode(value).unwrap())
-            .unwrap(),
-    )
-    .unwrap()
+fn decrypt(value: &str, rsa_private_key: &RsaPrivateKey) -> Result<String, String> {
+    let bytes = match value.strip_prefix(ENVELOPE_V1_PREFIX) {
+        Some(envelope) => decrypt_v1(envelope, rsa_private_key)?,
+        // Values encrypted before the envelope was introduced are encrypted directly using RSA
+        None => rsa_private_key
+            .decrypt(Pkcs1v15Encrypt, &decode_base64(value)?)
+            .map_err(|e| format!("Failed to decrypt value: {e}"))?,
+    };
+
+    String::from_utf8(bytes).map_err(|_| "Decrypted value is not valid UTF-8".to_string())
+}
+
+fn decrypt_v1(envelope: &str, rsa_private_key: &RsaPrivateKey) -> Result<Vec<u8>, String> {
+    let bytes = decode_base64(envelope)?;
+    let key_length = rsa_private_key.size();
+    if bytes.len() < key_length + NONCE_LENGTH {
+        return Err("Encrypted value too short".to_string());
+    }
+    let (encrypted_key, rest) = bytes.split_at(key_length);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+
+    let aes_key: [u8; 32] = rsa_private_key
+        .decrypt(Oaep::new::<Sha256>(), encrypted_key)
+        .map_err(|e| format!("Failed to decrypt key: {e}"))?
+        .try_into()
+        .map_err(|_| "Decrypted key has invalid length".to_string())?;
+
+    Aes256Gcm::new(&aes_key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt value".to_string())
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>, String> {
+    BASE64_STANDARD
+        .decode(value)
+        .map_err(|e| format!("Invalid base64: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_then_decrypt() {
+        let mut rng = rand::thread_rng();
+        let rsa_private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        let rsa_public_key = rsa_private_key.to_public_key();
+        // Longer than can be encrypted directly using RSA
+        let value = "a".repeat(1000);
+
+        let encrypted = encrypt(&value, &rsa_public_key, &mut rng);
+
+        assert!(encrypted.starts_with(ENVELOPE_V1_PREFIX));
+        assert_eq!(decrypt(&encrypted, &rsa_private_key).unwrap(), value);
+    }
+
+    #[test]
+    fn decrypt_legacy_value() {
+        let mut rng = rand::thread_rng();
+        let rsa_private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        let encrypted = BASE64_STANDARD.encode(
+            rsa_private_key
+                .to_public_key()
+                .encrypt(&mut rng, Pkcs1v15Encrypt, b"secret")
+                .unwrap(),
+        );
+
+        assert_eq!(decrypt(&encrypted, &rsa_private_key).unwrap(), "secret");
+    }
+
+    #[test]
+    fn decrypt_fails_if_tampered_with() {
+        let mut rng = rand::thread_rng();
+        let rsa_private_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
+        let encrypted = encrypt("secret", &rsa_private_key.to_public_key(), &mut rng);
+
+        let mut bytes = BASE64_STANDARD
+            .decode(encrypted.strip_prefix(ENVELOPE_V1_PREFIX).unwrap())
+            .unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        let tampered = format!("{ENVELOPE_V1_PREFIX}{}", BASE64_STANDARD.encode(bytes));
+
+        assert!(decrypt(&tampered, &rsa_private_key).is_err());
+        assert!(decrypt("not base64!", &rsa_private_key).is_err());
+    }
 }
@@ -1,4 +1,7 @@
-use crate::EncryptedEmailSenderConfig;
+use crate::{
+    Branding, EmailRateLimitPolicy, EmailSenderPublicKey, EncryptedEmailSenderConfig,
+    MagicLinkExpiryPolicy, MagicLinkSignatureScheme, ThrottlePolicy, VerificationCodeFormat,
+};
 use candid::{CandidType, Deserialize, Principal};
 use serde::Serialize;
 
@@ -26,14 +29,36 @@ impl InitOrUpgradeArgs {
 
 #[derive(CandidType, Serialize, Deserialize, Debug)]
 pub struct InitArgs {
+    // Either an RSA or an Ed25519 key
     pub email_sender_public_key_pem: String,
     // Only use this for testing
     pub salt: Option<[u8; 32]>,
     pub whitelisted_principals: Vec<Principal>,
+    pub email_rate_limit_policy: Option<EmailRateLimitPolicy>,
+    pub throttle_policy: Option<ThrottlePolicy>,
+    pub verification_code_format: Option<VerificationCodeFormat>,
+    // The number of incorrect codes after which a magic link is locked, defaults to 3
+    pub max_incorrect_code_attempts: Option<u32>,
+    pub magic_link_expiry_policy: Option<MagicLinkExpiryPolicy>,
+    pub branding: Option<Branding>,
+    // If enabled, links contain a short token which the canister resolves to the full magic link
+    pub short_magic_links: Option<bool>,
+    pub magic_link_signature_scheme: Option<MagicLinkSignatureScheme>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug, Default)]
 pub struct UpgradeArgs {
+    // Replaces the accepted email sender keys with this single key
     pub email_sender_public_key_pem: Option<String>,
+    // Replaces the accepted email sender keys, cannot be combined with `email_sender_public_key_pem`
+    pub email_sender_public_keys: Option<Vec<EmailSenderPublicKey>>,
     pub email_sender_config: Option<EncryptedEmailSenderConfig>,
+    pub email_rate_limit_policy: Option<EmailRateLimitPolicy>,
+    pub throttle_policy: Option<ThrottlePolicy>,
+    pub verification_code_format: Option<VerificationCodeFormat>,
+    pub max_incorrect_code_attempts: Option<u32>,
+    pub magic_link_expiry_policy: Option<MagicLinkExpiryPolicy>,
+    pub branding: Option<Branding>,
+    pub short_magic_links: Option<bool>,
+    pub magic_link_signature_scheme: Option<MagicLinkSignatureScheme>,
 }
@@ -1,9 +1,12 @@
-use crate::EmailSenderConfigPublic;
+use crate::{EmailSenderConfigPublic, EmailSenderPublicKey};
 use candid::CandidType;
 use serde::{Deserialize, Serialize};
 
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct EmailSenderConfigResponse {
+    // Despite the name, this may also be an Ed25519 key. If multiple keys are accepted this is the
+    // most recently added one.
     pub email_sender_rsa_public_key: String,
+    pub email_sender_public_keys: Vec<EmailSenderPublicKey>,
     pub email_sender_config: Option<EmailSenderConfigPublic>,
 }
@@ -0,0 +1,24 @@
+use crate::TimestampMillis;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub enum GetEmailStatsArgs {
+    Email(String),
+    Principal(Principal),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub enum GetEmailStatsResponse {
+    Success(EmailStats),
+    NotFound,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmailStats {
+    pub first_seen: TimestampMillis,
+    pub emails_sent: u32,
+    pub latest_email_sent: TimestampMillis,
+    pub successful_links: u32,
+    pub latest_successful_link: Option<TimestampMillis>,
+}
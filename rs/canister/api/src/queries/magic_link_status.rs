@@ -0,0 +1,19 @@
+use crate::TimestampNanos;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub struct MagicLinkStatusArgs {
+    pub email: String,
+    #[serde(with = "serde_bytes")]
+    pub session_key: Vec<u8>,
+    pub expiration: TimestampNanos,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub enum MagicLinkStatusResponse {
+    Pending,
+    Verified,
+    Expired,
+    Unknown,
+}
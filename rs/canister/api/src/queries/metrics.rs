@@ -0,0 +1,11 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Metrics {
+    pub total_emails_sent: u64,
+    pub total_successful_links: u64,
+    pub success_ratio: f64,
+    pub active_magic_links: u64,
+    pub distinct_users: u64,
+}
@@ -1,7 +1,15 @@
 mod email_sender_config;
 mod get_delegation;
+mod get_email_stats;
 mod get_principal;
+mod magic_link_status;
+mod metrics;
+mod rsa_public_keys;
 
 pub use email_sender_config::*;
 pub use get_delegation::*;
+pub use get_email_stats::*;
 pub use get_principal::*;
+pub use magic_link_status::*;
+pub use metrics::*;
+pub use rsa_public_keys::*;
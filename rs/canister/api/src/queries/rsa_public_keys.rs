@@ -0,0 +1,16 @@
+use crate::TimestampMillis;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RsaPublicKeysResponse {
+    pub current: String,
+    // Keys replaced by a rotation, which are still accepted until the links they signed expire
+    pub previous: Vec<PreviousRsaPublicKey>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PreviousRsaPublicKey {
+    pub public_key_pem: String,
+    pub valid_until: TimestampMillis,
+}
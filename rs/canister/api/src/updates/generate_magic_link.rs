@@ -8,14 +8,17 @@ pub struct GenerateMagicLinkArgs {
     #[serde(with = "serde_bytes")]
     pub session_key: Vec<u8>,
     pub max_time_to_live: Option<Nanoseconds>,
+    pub magic_link_time_to_live: Option<Milliseconds>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug)]
 pub enum GenerateMagicLinkResponse {
     Success(GenerateMagicLinkSuccess),
     Blocked(Milliseconds),
+    Throttled(Milliseconds),
     EmailInvalid,
     FailedToSendEmail(String),
+    FailedToSignMagicLink(String),
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug)]
@@ -25,4 +28,5 @@ pub struct GenerateMagicLinkSuccess {
     pub user_key: Vec<u8>,
     pub expiration: TimestampNanos,
     pub code: String,
+    pub magic_link_expiration: TimestampMillis,
 }
@@ -10,6 +10,8 @@ pub struct HandleMagicLinkArgs {
 pub enum HandleMagicLinkResponse {
     Success,
     LinkExpired,
+    LinkAlreadyUsed,
     LinkInvalid(String),
     CodeIncorrect,
+    TooManyAttempts,
 }
@@ -1,5 +1,11 @@
 mod generate_magic_link;
 mod handle_magic_link;
+mod rotate_rsa_key;
+mod set_email_sender_config;
+mod set_email_sender_public_keys;
 
 pub use generate_magic_link::*;
 pub use handle_magic_link::*;
+pub use rotate_rsa_key::*;
+pub use set_email_sender_config::*;
+pub use set_email_sender_public_keys::*;
@@ -0,0 +1,9 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub enum RotateRsaKeyResponse {
+    // The PEM encoded public key of the new key
+    Success(String),
+    KeyNotInitialized,
+}
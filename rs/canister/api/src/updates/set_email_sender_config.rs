@@ -0,0 +1,9 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub enum SetEmailSenderConfigResponse {
+    Success,
+    KeyNotInitialized,
+    DecryptionFailed(String),
+}
@@ -0,0 +1,15 @@
+use crate::EmailSenderPublicKey;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub struct SetEmailSenderPublicKeysArgs {
+    pub keys: Vec<EmailSenderPublicKey>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub enum SetEmailSenderPublicKeysResponse {
+    Success,
+    NoKeys,
+    InvalidPublicKey(String),
+}
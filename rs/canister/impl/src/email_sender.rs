@@ -1,39 +1,84 @@
 use crate::env;
-use email_sender_core::EmailSender;
+use email_sender_core::{EmailSender, FailoverEmailSender};
 use magic_links::SignedMagicLink;
-use sign_in_with_email_canister::EmailSenderConfig;
-use std::sync::OnceLock;
+use sign_in_with_email_canister::{EmailSenderConfig, Milliseconds, ONE_MINUTE};
+use std::cell::RefCell;
+use std::rc::Rc;
 
-static EMAIL_SENDER: OnceLock<Box<dyn EmailSender>> = OnceLock::new();
+thread_local! {
+    static EMAIL_SENDER: RefCell<Option<Rc<dyn EmailSender>>> = RefCell::default();
+}
+
+const DEFAULT_MAX_CONSECUTIVE_FAILURES: u32 = 3;
+const DEFAULT_FAILOVER_COOLDOWN: Milliseconds = 5 * ONE_MINUTE;
 
 pub fn init_from_config(config: EmailSenderConfig) {
-    #[allow(unused_variables)]
+    let mut senders = Vec::new();
+    build_senders(config, &mut senders);
+
+    EMAIL_SENDER.set(senders.pop().map(Rc::from));
+}
+
+#[allow(unused_variables)]
+fn build_senders(config: EmailSenderConfig, senders: &mut Vec<Box<dyn EmailSender>>) {
     match config {
         EmailSenderConfig::Aws(aws) => {
             #[cfg(feature = "email_sender_aws")]
             {
-                init(email_sender_aws::AwsEmailSender::new(
+                senders.push(Box::new(email_sender_aws::AwsEmailSender::new(
                     aws.region,
                     aws.function_url,
                     aws.access_key,
                     aws.secret_key,
-                ));
+                )));
             }
 
             #[cfg(not(feature = "email_sender_aws"))]
             panic!("Canister must be built with the \"aws\" feature enabled in order to use the AWS email sender");
         }
+        EmailSenderConfig::HttpRelay(relay) => {
+            #[cfg(feature = "email_sender_http_relay")]
+            {
+                senders.push(Box::new(
+                    email_sender_http_relay::HttpRelayEmailSender::new(relay.url, relay.hmac_key),
+                ));
+            }
+
+            #[cfg(not(feature = "email_sender_http_relay"))]
+            panic!("Canister must be built with the \"http_relay\" feature enabled in order to use the HTTP relay email sender");
+        }
+        EmailSenderConfig::Failover(failover) => {
+            assert!(
+                !failover.senders.is_empty(),
+                "At least one email sender must be provided for failover"
+            );
+
+            let mut failover_senders = Vec::new();
+            for config in failover.senders {
+                build_senders(config, &mut failover_senders);
+            }
+
+            senders.push(Box::new(FailoverEmailSender::new(
+                failover_senders,
+                failover
+                    .max_consecutive_failures
+                    .unwrap_or(DEFAULT_MAX_CONSECUTIVE_FAILURES),
+                failover.cooldown.unwrap_or(DEFAULT_FAILOVER_COOLDOWN),
+                env::now,
+            )));
+        }
     }
 }
 
+// Replaces any existing email sender, sends already in progress complete using the previous sender
 pub fn init(email_sender: impl EmailSender + 'static) {
-    EMAIL_SENDER
-        .set(Box::new(email_sender))
-        .unwrap_or_else(|_| panic!("Email sender already set"));
+    EMAIL_SENDER.set(Some(Rc::new(email_sender)));
 }
 
 pub async fn send_magic_link(magic_link: SignedMagicLink) -> Result<(), String> {
-    let sender = EMAIL_SENDER.get().expect("Email sender has not been set");
+    let sender = EMAIL_SENDER
+        .with_borrow(|s| s.clone())
+        .expect("Email sender has not been set");
 
     sender.send(magic_link, env::now()).await
 }
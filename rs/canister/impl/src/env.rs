@@ -18,3 +18,7 @@ pub fn canister_id() -> Principal {
 pub fn caller() -> Principal {
     ic_cdk::api::caller()
 }
+
+pub fn cycles_balance() -> u128 {
+    ic_cdk::api::canister_balance128()
+}
@@ -1,4 +1,4 @@
-use crate::state;
+use crate::{env, state};
 
 pub fn caller_is_whitelisted() -> Result<(), String> {
     if state::read(|state| state.is_caller_whitelisted()) {
@@ -7,3 +7,11 @@ pub fn caller_is_whitelisted() -> Result<(), String> {
         Err("Caller is not whitelisted".to_string())
     }
 }
+
+pub fn caller_is_controller() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&env::caller()) {
+        Ok(())
+    } else {
+        Err("Caller is not a controller".to_string())
+    }
+}
@@ -0,0 +1,260 @@
+use crate::model::certified_http_responses::CertifiedHttpResponses;
+use crate::state::AuthResult;
+use crate::translations::{Language, Translations};
+use ic_http_certification::{HttpCertificationPath, HttpResponse};
+use sign_in_with_email_canister::Branding;
+
+pub const AUTH_PATH: &str = "/auth";
+pub const METRICS_PATH: &str = "/metrics";
+pub const NOT_FOUND: &str = "not_found";
+const CODE_ENTRY: &str = "code_entry";
+
+// Builds the set of responses which can be served from the query path. Any other `/auth` result
+// is served by upgrading to an update call, which doesn't require certification.
+pub fn certified_responses(branding: &Branding) -> CertifiedHttpResponses {
+    let mut responses = CertifiedHttpResponses::default();
+
+    for result in [
+        AuthResult::RequiresUpgrade,
+        AuthResult::LinkExpired,
+        AuthResult::LinkAlreadyUsed,
+        AuthResult::TooManyAttempts,
+        // Served without the reason the link is invalid, which is only included from update calls
+        AuthResult::LinkInvalid(String::new()),
+    ] {
+        for language in Language::ALL {
+            responses.certify(
+                auth_response_key(&result, language),
+                HttpCertificationPath::exact(AUTH_PATH),
+                auth_response(&result, branding, language),
+            );
+        }
+    }
+    for language in Language::ALL {
+        responses.certify(
+            code_entry_key(language),
+            HttpCertificationPath::exact(AUTH_PATH),
+            code_entry_response(branding, language),
+        );
+    }
+    responses.skip(
+        METRICS_PATH.to_string(),
+        HttpCertificationPath::exact(METRICS_PATH),
+    );
+    responses.certify(
+        NOT_FOUND.to_string(),
+        HttpCertificationPath::wildcard("/"),
+        not_found(),
+    );
+
+    responses
+}
+
+pub fn auth_response_key(result: &AuthResult, language: Language) -> String {
+    format!("{}_{}", result.label(), language.code())
+}
+
+pub fn code_entry_key(language: Language) -> String {
+    format!("{CODE_ENTRY}_{}", language.code())
+}
+
+// A form which posts the code back to the same URL, so that a link can be opened on a different
+// device from the one showing the code
+pub fn code_entry_response(branding: &Branding, language: Language) -> HttpResponse {
+    let t = language.translations();
+    let form = format!(
+        r#"<form method="post">
+<input name="c" aria-label="{title}" autocomplete="one-time-code" autocapitalize="characters" spellcheck="false" required autofocus>
+<button type="submit">{submit}</button>
+</form>"#,
+        title = escape_html(t.code_entry_title),
+        submit = escape_html(t.code_entry_submit),
+    );
+    let body = render_page(
+        branding,
+        language,
+        t,
+        t.code_entry_title,
+        t.code_entry_message,
+        &form,
+    );
+
+    html_response(200, language, body)
+}
+
+pub fn auth_response(result: &AuthResult, branding: &Branding, language: Language) -> HttpResponse {
+    let t = language.translations();
+    let (status_code, title, message) = match result {
+        AuthResult::Success => (200, t.success_title, t.success_message.to_string()),
+        AuthResult::RequiresUpgrade => {
+            return HttpResponse {
+                status_code: 200,
+                headers: vec![
+                    ("content-type".to_string(), "text/plain".to_string()),
+                    ("content-length".to_string(), "0".to_string()),
+                ],
+                body: Vec::new(),
+                upgrade: Some(true),
+            }
+        }
+        AuthResult::LinkExpired => (
+            400,
+            t.link_expired_title,
+            t.link_expired_message.to_string(),
+        ),
+        AuthResult::LinkAlreadyUsed => (
+            400,
+            t.link_already_used_title,
+            t.link_already_used_message.to_string(),
+        ),
+        AuthResult::LinkInvalid(error) if error.is_empty() => (
+            400,
+            t.link_invalid_title,
+            t.link_invalid_message.to_string(),
+        ),
+        AuthResult::LinkInvalid(error) => (
+            400,
+            t.link_invalid_title,
+            format!("{} ({error})", t.link_invalid_message),
+        ),
+        AuthResult::CodeIncorrect => (
+            400,
+            t.code_incorrect_title,
+            t.code_incorrect_message.to_string(),
+        ),
+        AuthResult::TooManyAttempts => (
+            400,
+            t.too_many_attempts_title,
+            t.too_many_attempts_message.to_string(),
+        ),
+    };
+
+    // Only link back to the app once the user has signed in
+    let return_link = match (result, &branding.return_url) {
+        (AuthResult::Success, Some(url)) => format!(
+            r#"<a class="return" href="{}">{}</a>"#,
+            escape_html(url),
+            escape_html(&with_app_name(t.return_to_app, branding, t))
+        ),
+        _ => String::new(),
+    };
+    let body = render_page(branding, language, t, title, &message, &return_link);
+
+    html_response(status_code, language, body)
+}
+
+fn html_response(status_code: u16, language: Language, body: String) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            (
+                "content-type".to_string(),
+                "text/html; charset=utf-8".to_string(),
+            ),
+            ("content-length".to_string(), body.len().to_string()),
+            ("content-language".to_string(), language.code().to_string()),
+            ("vary".to_string(), "accept-language".to_string()),
+        ],
+        body: body.into_bytes(),
+        upgrade: None,
+    }
+}
+
+pub fn metrics_response(body: String) -> HttpResponse {
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            (
+                "content-type".to_string(),
+                "text/plain; version=0.0.4".to_string(),
+            ),
+            ("content-length".to_string(), body.len().to_string()),
+        ],
+        body: body.into_bytes(),
+        upgrade: None,
+    }
+}
+
+pub fn not_found() -> HttpResponse {
+    HttpResponse {
+        status_code: 404,
+        headers: Vec::new(),
+        body: Vec::new(),
+        upgrade: None,
+    }
+}
+
+fn render_page(
+    branding: &Branding,
+    language: Language,
+    t: &Translations,
+    title: &str,
+    message: &str,
+    // Trusted HTML which is inserted after the message
+    extra_html: &str,
+) -> String {
+    let logo = branding
+        .logo_url
+        .as_ref()
+        .map(|url| {
+            format!(
+                r#"<img class="logo" src="{}" alt="{}">"#,
+                escape_html(url),
+                escape_html(&with_app_name("{app}", branding, t))
+            )
+        })
+        .unwrap_or_default();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="{lang}">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; display: flex; justify-content: center; margin: 0; padding: 4rem 1rem; background: #f5f5f5; color: #222; }}
+main {{ max-width: 32rem; text-align: center; background: #fff; border-radius: 0.5rem; padding: 2rem; box-shadow: 0 1px 4px rgba(0, 0, 0, 0.1); }}
+.logo {{ max-height: 4rem; margin-bottom: 1rem; }}
+.return {{ display: inline-block; margin-top: 1rem; }}
+input {{ font-size: 1.5rem; letter-spacing: 0.2rem; text-align: center; width: 12rem; padding: 0.5rem; margin-bottom: 1rem; }}
+button {{ display: block; margin: 0 auto; font-size: 1rem; padding: 0.5rem 2rem; }}
+</style>
+</head>
+<body>
+<main>
+{logo}
+<h1>{title}</h1>
+<p>{message}</p>
+{extra_html}
+</main>
+</body>
+</html>
+"#,
+        lang = language.code(),
+        title = escape_html(&with_app_name(title, branding, t)),
+        message = escape_html(&with_app_name(message, branding, t)),
+    )
+}
+
+fn with_app_name(text: &str, branding: &Branding, t: &Translations) -> String {
+    text.replace(
+        "{app}",
+        branding.app_name.as_deref().unwrap_or(t.app_fallback),
+    )
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
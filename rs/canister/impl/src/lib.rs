@@ -1,14 +1,19 @@
+use magic_links::DoubleSignedMagicLink;
 use querystring::QueryParams;
 
 mod email_sender;
 mod env;
 mod guards;
+mod http_responses;
 mod lifecycle;
+mod magic_link_signer;
 mod memory;
 mod model;
+mod prometheus;
 mod queries;
 mod rng;
 mod state;
+mod translations;
 mod updates;
 
 type Hash = [u8; 32];
@@ -20,6 +25,24 @@ fn get_query_param_value(params: &QueryParams, key: &str) -> Option<String> {
         .map(|(_, v)| v.to_string())
 }
 
+fn parse_magic_link(params: &QueryParams) -> Result<DoubleSignedMagicLink, String> {
+    let param = |key: &str| {
+        get_query_param_value(params, key).ok_or_else(|| format!("Parameter '{key}' missing"))
+    };
+
+    if let Some(short_token) = get_query_param_value(params, "t") {
+        let signed_magic_link = state::read(|s| s.resolve_short_link(&short_token))
+            .ok_or_else(|| "Link not found".to_string())?;
+        DoubleSignedMagicLink::from_short_token(signed_magic_link, &param("s")?)
+    } else if let Some(link) = get_query_param_value(params, "l") {
+        DoubleSignedMagicLink::from_base64(&link)
+    } else {
+        // Links sent before the compact encoding was introduced
+        DoubleSignedMagicLink::from_hex_strings(&param("m")?, &param("s1")?, &param("s2")?)
+    }
+    .map_err(|error| error.to_string())
+}
+
 #[cfg(test)]
 mod generate_candid_file {
     use candid::Principal;
@@ -1,26 +1,36 @@
 use crate::state::State;
-use crate::{email_sender, env, rng, state};
+use crate::{email_sender, env, http_responses, rng, state};
 use email_sender_core::NullEmailSender;
 use ic_cdk::init;
-use rsa::pkcs8::DecodePublicKey;
-use rsa::RsaPublicKey;
-use sign_in_with_email_canister::InitOrUpgradeArgs;
+use magic_links::PublicKey;
+use sign_in_with_email_canister::{InitOrUpgradeArgs, DEFAULT_MAX_INCORRECT_CODE_ATTEMPTS};
 use std::time::Duration;
 
 #[init]
 fn init(args: InitOrUpgradeArgs) {
     let init_args = args.to_init_args();
-    let email_sender_public_key = RsaPublicKey::from_public_key_pem(
-        &init_args.email_sender_public_key_pem.replace("\\n", "\n"),
-    )
-    .unwrap();
+    let email_sender_public_key =
+        PublicKey::from_pem(&init_args.email_sender_public_key_pem.replace("\\n", "\n")).unwrap();
     let test_mode = init_args.salt.is_some();
 
-    state::init(State::new(
+    let mut state = State::new(
         email_sender_public_key,
         init_args.whitelisted_principals,
+        init_args.email_rate_limit_policy.unwrap_or_default(),
+        init_args.throttle_policy.unwrap_or_default(),
+        init_args.verification_code_format.unwrap_or_default(),
+        init_args
+            .max_incorrect_code_attempts
+            .unwrap_or(DEFAULT_MAX_INCORRECT_CODE_ATTEMPTS),
+        init_args.magic_link_expiry_policy.unwrap_or_default(),
+        init_args.branding.unwrap_or_default(),
+        init_args.short_magic_links.unwrap_or_default(),
+        init_args.magic_link_signature_scheme.unwrap_or_default(),
         test_mode,
-    ));
+    );
+    state.set_http_responses(http_responses::certified_responses(state.branding()));
+
+    state::init(state);
 
     if let Some(salt) = init_args.salt {
         email_sender::init(NullEmailSender::default());
@@ -46,6 +56,7 @@ fn set_salt(salt: [u8; 32], entropy: u64) {
 
     state::mutate(|s| {
         s.set_rsa_private_key(rng::generate_rsa_private_key());
+        s.set_ed25519_private_key(rng::generate_ed25519_private_key());
         s.set_salt(salt);
     });
 }
@@ -1,11 +1,13 @@
 use crate::lifecycle::READER_WRITER_BUFFER_SIZE;
 use crate::memory::get_upgrades_memory;
+use crate::model::email_sender_keys::EmailSenderKeys;
 use crate::state::State;
-use crate::{email_sender, env, rng, state};
+use crate::{email_sender, env, http_responses, rng, state};
 use candid::Principal;
 use email_sender_core::NullEmailSender;
 use ic_cdk::post_upgrade;
 use ic_stable_structures::reader::{BufferedReader, Reader};
+use magic_links::PublicKey;
 use serde::Deserialize;
 use sign_in_with_email_canister::InitOrUpgradeArgs;
 
@@ -18,17 +20,73 @@ fn post_upgrade(args: InitOrUpgradeArgs) {
     let mut deserializer = rmp_serde::Deserializer::new(reader);
 
     let mut state = State::deserialize(&mut deserializer).unwrap();
+    state.migrate_to_stable_memory();
     let entropy = if state.test_mode() { 0 } else { env::now() };
 
     rng::set_seed(state.salt(), entropy);
 
+    // Canisters whose salt has been set prior to Ed25519 keys being introduced won't have one yet
+    if state.rsa_private_key().is_some() && state.ed25519_private_key().is_none() {
+        state.set_ed25519_private_key(rng::generate_ed25519_private_key());
+    }
+
+    match (
+        upgrade_args.email_sender_public_key_pem,
+        upgrade_args.email_sender_public_keys,
+    ) {
+        (Some(_), Some(_)) => panic!(
+            "Only one of email_sender_public_key_pem and email_sender_public_keys can be set"
+        ),
+        (Some(pem), None) => {
+            let public_key = PublicKey::from_pem(&pem.replace("\\n", "\n")).unwrap();
+            state.set_email_sender_public_keys(EmailSenderKeys::single(public_key));
+        }
+        (None, Some(keys)) => {
+            state.set_email_sender_public_keys(EmailSenderKeys::try_from(keys).unwrap());
+        }
+        (None, None) => {}
+    }
+
     if let Some(config) = upgrade_args.email_sender_config {
-        let rsa_private_key = state
+        // Fail the upgrade rather than silently keeping the existing config
+        let config = state
             .rsa_private_key()
-            .clone()
-            .expect("RSA private key not set");
+            .ok_or_else(|| "RSA private key not set".to_string())
+            .and_then(|k| config.decrypt(&k))
+            .unwrap_or_else(|error| panic!("Failed to decrypt email sender config: {error}"));
+        state.set_email_sender_config(config);
+    }
+
+    if let Some(policy) = upgrade_args.email_rate_limit_policy {
+        state.set_email_rate_limit_policy(policy);
+    }
+
+    if let Some(policy) = upgrade_args.throttle_policy {
+        state.set_throttle_policy(policy);
+    }
+
+    if let Some(format) = upgrade_args.verification_code_format {
+        state.set_verification_code_format(format);
+    }
+
+    if let Some(max_attempts) = upgrade_args.max_incorrect_code_attempts {
+        state.set_max_incorrect_code_attempts(max_attempts);
+    }
+
+    if let Some(policy) = upgrade_args.magic_link_expiry_policy {
+        state.set_magic_link_expiry_policy(policy);
+    }
 
-        state.set_email_sender_config(config.decrypt(&rsa_private_key));
+    if let Some(branding) = upgrade_args.branding {
+        state.set_branding(branding);
+    }
+
+    if let Some(enabled) = upgrade_args.short_magic_links {
+        state.set_short_magic_links(enabled);
+    }
+
+    if let Some(scheme) = upgrade_args.magic_link_signature_scheme {
+        state.set_magic_link_signature_scheme(scheme);
     }
 
     if let Some(config) = state.email_sender_config().cloned() {
@@ -45,5 +103,9 @@ fn post_upgrade(args: InitOrUpgradeArgs) {
     };
     state.set_whitelisted_principals(vec![Principal::from_text(identity_canister).unwrap()]);
 
+    // Certified data is cleared during upgrades so must be set again, this also covers the restored
+    // signatures
+    state.set_http_responses(http_responses::certified_responses(state.branding()));
+
     state::init(state);
 }
@@ -0,0 +1,69 @@
+use crate::{env, state};
+use ic_cdk::api::management_canister::schnorr::{
+    schnorr_public_key, sign_with_schnorr, SchnorrAlgorithm, SchnorrKeyId,
+    SchnorrPublicKeyArgument, SignWithSchnorrArgument,
+};
+use magic_links::{MagicLink, PublicKey, SignedMagicLink, SigningKey};
+
+pub enum MagicLinkSigner {
+    Local(SigningKey),
+    Threshold {
+        key_name: String,
+        // The public key is fetched the first time the key is used so that links can be verified
+        public_key_cached: bool,
+    },
+}
+
+pub async fn sign(
+    signer: MagicLinkSigner,
+    magic_link: MagicLink,
+) -> Result<SignedMagicLink, String> {
+    match signer {
+        MagicLinkSigner::Local(signing_key) => Ok(magic_link.sign(&signing_key)),
+        MagicLinkSigner::Threshold {
+            key_name,
+            public_key_cached,
+        } => {
+            if !public_key_cached {
+                let public_key = threshold_public_key(key_name.clone()).await?;
+                state::mutate(|s| {
+                    s.set_threshold_public_key(key_name.clone(), public_key, env::now())
+                });
+            }
+
+            // Each signature costs cycles, see `ThrottlePolicy`
+            let (response,) = sign_with_schnorr(SignWithSchnorrArgument {
+                message: magic_link.hash().to_vec(),
+                derivation_path: Vec::new(),
+                key_id: key_id(key_name),
+            })
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+
+            Ok(magic_link.with_signature(response.signature))
+        }
+    }
+}
+
+async fn threshold_public_key(key_name: String) -> Result<PublicKey, String> {
+    let (response,) = schnorr_public_key(SchnorrPublicKeyArgument {
+        canister_id: None,
+        derivation_path: Vec::new(),
+        key_id: key_id(key_name),
+    })
+    .await
+    .map_err(|e| format!("{e:?}"))?;
+
+    response
+        .public_key
+        .try_into()
+        .map(PublicKey::Ed25519)
+        .map_err(|_| "Threshold public key is not a valid Ed25519 key".to_string())
+}
+
+fn key_id(key_name: String) -> SchnorrKeyId {
+    SchnorrKeyId {
+        algorithm: SchnorrAlgorithm::Ed25519,
+        name: key_name,
+    }
+}
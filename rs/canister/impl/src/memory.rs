@@ -4,6 +4,14 @@ use ic_stable_structures::{
 };
 
 const UPGRADES: MemoryId = MemoryId::new(0);
+const EMAIL_STATS: MemoryId = MemoryId::new(1);
+const ACTIVE_MAGIC_LINKS: MemoryId = MemoryId::new(2);
+const USED_MAGIC_LINKS: MemoryId = MemoryId::new(3);
+const INCORRECT_CODE_ATTEMPTS: MemoryId = MemoryId::new(4);
+const MAGIC_LINK_EXPIRATIONS: MemoryId = MemoryId::new(5);
+const SHORT_MAGIC_LINKS: MemoryId = MemoryId::new(6);
+const PRINCIPAL_SEEDS: MemoryId = MemoryId::new(7);
+const WASM_PAGE_SIZE_BYTES: u64 = 64 * 1024;
 
 pub type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -16,6 +24,50 @@ pub fn get_upgrades_memory() -> Memory {
     get_memory(UPGRADES)
 }
 
+pub fn get_email_stats_memory() -> Memory {
+    get_memory(EMAIL_STATS)
+}
+
+pub fn get_active_magic_links_memory() -> Memory {
+    get_memory(ACTIVE_MAGIC_LINKS)
+}
+
+pub fn get_used_magic_links_memory() -> Memory {
+    get_memory(USED_MAGIC_LINKS)
+}
+
+pub fn get_incorrect_code_attempts_memory() -> Memory {
+    get_memory(INCORRECT_CODE_ATTEMPTS)
+}
+
+pub fn get_magic_link_expirations_memory() -> Memory {
+    get_memory(MAGIC_LINK_EXPIRATIONS)
+}
+
+pub fn get_short_magic_links_memory() -> Memory {
+    get_memory(SHORT_MAGIC_LINKS)
+}
+
+pub fn get_principal_seeds_memory() -> Memory {
+    get_memory(PRINCIPAL_SEEDS)
+}
+
+pub fn heap_memory_size_bytes() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE_BYTES
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+pub fn stable_memory_size_bytes() -> u64 {
+    ic_cdk::api::stable::stable_size() * WASM_PAGE_SIZE_BYTES
+}
+
 fn get_memory(id: MemoryId) -> Memory {
     MEMORY_MANAGER.with(|m| m.get(id))
 }
@@ -0,0 +1,141 @@
+use crate::Hash;
+use ic_certification::{fork, pruned};
+use ic_http_certification::utils::add_v2_certificate_header;
+use ic_http_certification::{
+    DefaultCelBuilder, DefaultResponseCertification, HttpCertification, HttpCertificationPath,
+    HttpCertificationTree, HttpCertificationTreeEntry, HttpResponse,
+    CERTIFICATE_EXPRESSION_HEADER_NAME,
+};
+use std::collections::HashMap;
+
+// Holds the static responses served from the query path of `http_request`, along with the tree
+// used to certify them, so that the HTTP gateway can verify the responses it passes on. Dynamic
+// responses are served with certification explicitly skipped.
+#[derive(Default)]
+pub struct CertifiedHttpResponses {
+    tree: HttpCertificationTree,
+    responses: HashMap<String, CertifiedResponse>,
+    skipped: HashMap<String, HttpCertificationPath<'static>>,
+}
+
+struct CertifiedResponse {
+    path: HttpCertificationPath<'static>,
+    certification: HttpCertification,
+    response: HttpResponse,
+}
+
+impl CertifiedHttpResponses {
+    pub fn certify(
+        &mut self,
+        key: String,
+        path: HttpCertificationPath<'static>,
+        mut response: HttpResponse,
+    ) {
+        let cel_expr = DefaultCelBuilder::response_only_certification()
+            .with_response_certification(DefaultResponseCertification::response_header_exclusions(
+                vec![],
+            ))
+            .build();
+
+        response.headers.push((
+            CERTIFICATE_EXPRESSION_HEADER_NAME.to_string(),
+            cel_expr.to_string(),
+        ));
+
+        let certification = HttpCertification::response_only(&cel_expr, &response, None).unwrap();
+        self.tree
+            .insert(&HttpCertificationTreeEntry::new(&path, &certification));
+
+        self.responses.insert(
+            key,
+            CertifiedResponse {
+                path,
+                certification,
+                response,
+            },
+        );
+    }
+
+    pub fn skip(&mut self, key: String, path: HttpCertificationPath<'static>) {
+        self.tree.insert(&HttpCertificationTreeEntry::new(
+            &path,
+            &HttpCertification::skip(),
+        ));
+
+        self.skipped.insert(key, path);
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        self.tree.root_hash()
+    }
+
+    // `sibling_root_hash` is the root hash of the rest of the certified data, which sits to the
+    // right of the HTTP certification tree
+    pub fn get(
+        &self,
+        key: &str,
+        request_path: &str,
+        sibling_root_hash: Hash,
+        data_certificate: Option<&[u8]>,
+    ) -> Option<HttpResponse> {
+        let certified = self.responses.get(key)?;
+        let mut response = certified.response.clone();
+
+        self.add_certificate_header(
+            &mut response,
+            &certified.path,
+            &certified.certification,
+            request_path,
+            sibling_root_hash,
+            data_certificate,
+        );
+        Some(response)
+    }
+
+    pub fn skip_certification(
+        &self,
+        key: &str,
+        mut response: HttpResponse,
+        request_path: &str,
+        sibling_root_hash: Hash,
+        data_certificate: Option<&[u8]>,
+    ) -> HttpResponse {
+        let path = self.skipped.get(key).expect("Path not registered");
+
+        response.headers.push((
+            CERTIFICATE_EXPRESSION_HEADER_NAME.to_string(),
+            DefaultCelBuilder::skip_certification().to_string(),
+        ));
+
+        self.add_certificate_header(
+            &mut response,
+            path,
+            &HttpCertification::skip(),
+            request_path,
+            sibling_root_hash,
+            data_certificate,
+        );
+        response
+    }
+
+    fn add_certificate_header(
+        &self,
+        response: &mut HttpResponse,
+        path: &HttpCertificationPath<'static>,
+        certification: &HttpCertification,
+        request_path: &str,
+        sibling_root_hash: Hash,
+        data_certificate: Option<&[u8]>,
+    ) {
+        // The data certificate is only available in non-replicated query calls
+        let Some(data_certificate) = data_certificate else {
+            return;
+        };
+
+        let entry = HttpCertificationTreeEntry::new(path, certification);
+        let witness = self.tree.witness(&entry, request_path).unwrap();
+        let tree = fork(witness, pruned(sibling_root_hash));
+
+        add_v2_certificate_header(data_certificate, response, &tree, &path.to_expr_path());
+    }
+}
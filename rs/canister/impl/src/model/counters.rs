@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Running totals which are exposed via the `/metrics` endpoint. These are only ever incremented
+// during update calls, since changes made during queries are discarded, meaning `auth_results`
+// doesn't include the results of auth requests which are handled entirely in the query path.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Counters {
+    emails_sent: u64,
+    emails_failed: u64,
+    auth_results: BTreeMap<String, u64>,
+}
+
+impl Counters {
+    pub fn emails_sent(&self) -> u64 {
+        self.emails_sent
+    }
+
+    pub fn emails_failed(&self) -> u64 {
+        self.emails_failed
+    }
+
+    pub fn auth_results(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.auth_results.iter().map(|(k, v)| (k.as_str(), *v))
+    }
+
+    pub fn record_email_sent(&mut self) {
+        self.emails_sent += 1;
+    }
+
+    pub fn record_email_failed(&mut self) {
+        self.emails_failed += 1;
+    }
+
+    pub fn record_auth_result(&mut self, result: &str) {
+        *self.auth_results.entry(result.to_string()).or_default() += 1;
+    }
+}
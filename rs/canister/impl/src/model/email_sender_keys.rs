@@ -0,0 +1,97 @@
+use magic_links::PublicKey;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Deserializer, Serialize};
+use sign_in_with_email_canister::{EmailSenderPublicKey, TimestampMillis};
+
+// The keys which the email sender may sign magic links with, ordered from oldest to newest
+#[derive(Serialize, Clone)]
+pub struct EmailSenderKeys {
+    keys: Vec<EmailSenderKey>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct EmailSenderKey {
+    public_key: PublicKey,
+    valid_from: Option<TimestampMillis>,
+    valid_until: Option<TimestampMillis>,
+}
+
+impl EmailSenderKeys {
+    pub fn single(public_key: PublicKey) -> EmailSenderKeys {
+        EmailSenderKeys {
+            keys: vec![EmailSenderKey {
+                public_key,
+                valid_from: None,
+                valid_until: None,
+            }],
+        }
+    }
+
+    pub fn valid_keys(&self, now: TimestampMillis) -> Vec<PublicKey> {
+        self.keys
+            .iter()
+            .filter(|k| k.valid_from.map_or(true, |from| from <= now))
+            .filter(|k| k.valid_until.map_or(true, |until| until >= now))
+            .map(|k| k.public_key.clone())
+            .collect()
+    }
+
+    pub fn latest(&self) -> &PublicKey {
+        &self.keys.last().unwrap().public_key
+    }
+
+    pub fn to_public(&self) -> Vec<EmailSenderPublicKey> {
+        self.keys
+            .iter()
+            .map(|k| EmailSenderPublicKey {
+                public_key_pem: k.public_key.to_pem(),
+                valid_from: k.valid_from,
+                valid_until: k.valid_until,
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<Vec<EmailSenderPublicKey>> for EmailSenderKeys {
+    type Error = String;
+
+    fn try_from(value: Vec<EmailSenderPublicKey>) -> Result<Self, Self::Error> {
+        if value.is_empty() {
+            return Err("At least one email sender public key must be provided".to_string());
+        }
+
+        let keys = value
+            .into_iter()
+            .map(|k| {
+                Ok(EmailSenderKey {
+                    public_key: PublicKey::from_pem(&k.public_key_pem.replace("\\n", "\n"))?,
+                    valid_from: k.valid_from,
+                    valid_until: k.valid_until,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(EmailSenderKeys { keys })
+    }
+}
+
+// Previously only a single key was stored, which prior to that could only be an RSA key
+impl<'de> Deserialize<'de> for EmailSenderKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum EmailSenderKeysCombined {
+            Rsa(RsaPublicKey),
+            Single(PublicKey),
+            Current { keys: Vec<EmailSenderKey> },
+        }
+
+        Ok(match EmailSenderKeysCombined::deserialize(deserializer)? {
+            EmailSenderKeysCombined::Rsa(rsa_public_key) => {
+                EmailSenderKeys::single(PublicKey::Rsa(rsa_public_key))
+            }
+            EmailSenderKeysCombined::Single(public_key) => EmailSenderKeys::single(public_key),
+            EmailSenderKeysCombined::Current { keys } => EmailSenderKeys { keys },
+        })
+    }
+}
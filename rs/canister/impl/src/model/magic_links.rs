@@ -1,12 +1,131 @@
+use crate::memory::{
+    get_active_magic_links_memory, get_email_stats_memory, get_incorrect_code_attempts_memory,
+    get_magic_link_expirations_memory, get_principal_seeds_memory, get_short_magic_links_memory,
+    get_used_magic_links_memory, Memory,
+};
 use crate::Hash;
+use candid::Principal;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use magic_links::SignedMagicLink;
 use serde::{Deserialize, Serialize};
-use sign_in_with_email_canister::TimestampMillis;
+use sign_in_with_email_canister::{
+    EmailRateLimitPolicy, MagicLinkStatusResponse, Metrics, Milliseconds, TimestampMillis,
+};
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
+use utils::{hash_bytes, hash_string};
 
-#[derive(Serialize, Deserialize, Default)]
+// Limits the work done by each call, expired entries which are left over are pruned by later calls
+const MAX_PRUNED_PER_CALL: usize = 100;
+
+// Everything lives in stable memory so that nothing needs to be serialized during upgrades
+#[derive(Serialize, Deserialize)]
 pub struct MagicLinks {
-    active: HashMap<(Hash, Hash), TimestampMillis>,
-    stats: BTreeMap<Hash, EmailStats>,
+    #[serde(skip, default = "init_active")]
+    active: StableBTreeMap<MagicLinkKey, ActiveMagicLink, Memory>,
+    #[serde(skip, default = "init_stats")]
+    stats: StableBTreeMap<Hash, EmailStats, Memory>,
+    // Maps the hash of each user's principal to their seed, so that stats can be looked up by
+    // principal without deriving the principal of every seed
+    #[serde(skip, default = "init_principal_seeds")]
+    principal_seeds: StableBTreeMap<Hash, Hash, Memory>,
+    // Running totals across all of the email stats, so that they don't need to be summed for each
+    // call to `metrics`. This is only `None` until it has been calculated from the existing stats.
+    #[serde(default)]
+    totals: Option<EmailTotals>,
+    // Maps the hash of each short token to the magic link it was issued for
+    #[serde(skip, default = "init_short_links")]
+    short_links: StableBTreeMap<Hash, ShortMagicLink, Memory>,
+    #[serde(skip, default = "init_used")]
+    used: StableBTreeMap<MagicLinkKey, TimestampMillis, Memory>,
+    #[serde(skip, default = "init_incorrect_code_attempts")]
+    incorrect_code_attempts: StableBTreeMap<MagicLinkKey, u32, Memory>,
+    // Indexes the entries above by when they expire, so that pruning doesn't need to scan them all
+    #[serde(skip, default = "init_expirations")]
+    expirations: StableBTreeMap<ExpiryKey, (), Memory>,
+    // TODO: Remove these once the state has been migrated to stable memory
+    #[serde(rename = "active", default, skip_serializing)]
+    legacy_active: HashMap<(Hash, Hash), ActiveMagicLink>,
+    #[serde(rename = "stats", default, skip_serializing)]
+    legacy_stats: BTreeMap<Hash, EmailStats>,
+    #[serde(rename = "used", default, skip_serializing)]
+    legacy_used: HashMap<(Hash, Hash), TimestampMillis>,
+    #[serde(rename = "incorrect_code_attempts", default, skip_serializing)]
+    legacy_incorrect_code_attempts: HashMap<(Hash, Hash), u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct MagicLinkKey {
+    seed: Hash,
+    msg_hash: Hash,
+}
+
+// Ordered by expiration first so that the expired entries are always at the front
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ExpiryKey {
+    expiration: TimestampMillis,
+    entry: ExpiringEntry,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ExpiringEntry {
+    Active(MagicLinkKey),
+    ShortLink(Hash),
+    Used(MagicLinkKey),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(from = "ActiveMagicLinkCombined")]
+struct ActiveMagicLink {
+    // The expiration of the delegation
+    expiration: TimestampMillis,
+    // The expiration of the link itself, after which the entry is pruned
+    link_expiration: TimestampMillis,
+}
+
+// Active magic links used to be stored as just the delegation expiration
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ActiveMagicLinkCombined {
+    Previous(TimestampMillis),
+    Current {
+        expiration: TimestampMillis,
+        link_expiration: TimestampMillis,
+    },
+}
+
+impl From<ActiveMagicLinkCombined> for ActiveMagicLink {
+    fn from(value: ActiveMagicLinkCombined) -> Self {
+        match value {
+            // The creation time of links sent prior to the upgrade wasn't stored, so the expiration
+            // of the delegation is the only known bound on when they expire
+            ActiveMagicLinkCombined::Previous(expiration) => ActiveMagicLink {
+                expiration,
+                link_expiration: expiration,
+            },
+            ActiveMagicLinkCombined::Current {
+                expiration,
+                link_expiration,
+            } => ActiveMagicLink {
+                expiration,
+                link_expiration,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShortMagicLink {
+    signed_magic_link: SignedMagicLink,
+    // The expiration of the link itself, after which the short token no longer resolves
+    expiration: TimestampMillis,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+struct EmailTotals {
+    emails_sent: u64,
+    successful_links: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -16,43 +135,501 @@ pub struct EmailStats {
     pub latest_email_sent: TimestampMillis,
     pub successful_links: u32,
     pub latest_successful_link: Option<TimestampMillis>,
+    // The number of emails sent since the rate limit backoff was last reset
+    #[serde(default)]
+    pub recent_emails_sent: u32,
+}
+
+impl Default for MagicLinks {
+    fn default() -> Self {
+        MagicLinks {
+            active: init_active(),
+            stats: init_stats(),
+            principal_seeds: init_principal_seeds(),
+            totals: Some(EmailTotals::default()),
+            short_links: init_short_links(),
+            used: init_used(),
+            incorrect_code_attempts: init_incorrect_code_attempts(),
+            expirations: init_expirations(),
+            legacy_active: HashMap::default(),
+            legacy_stats: BTreeMap::default(),
+            legacy_used: HashMap::default(),
+            legacy_incorrect_code_attempts: HashMap::default(),
+        }
+    }
 }
 
 impl MagicLinks {
+    // Moves any entries deserialized from the upgrade blob into their stable memory maps
+    pub fn migrate_to_stable_memory(&mut self, principal_from_seed: impl Fn(Hash) -> Principal) {
+        // Entries added before the expiry index existed are indexed the first time this runs
+        if self.expirations.is_empty() {
+            let active: Vec<_> = self
+                .active
+                .iter()
+                .map(|(key, link)| (link.link_expiration, ExpiringEntry::Active(key)))
+                .collect();
+            let short_links: Vec<_> = self
+                .short_links
+                .iter()
+                .map(|(token_hash, link)| (link.expiration, ExpiringEntry::ShortLink(token_hash)))
+                .collect();
+
+            for (expiration, entry) in active.into_iter().chain(short_links) {
+                self.expirations.insert(ExpiryKey { expiration, entry }, ());
+            }
+        }
+
+        for ((seed, msg_hash), link) in std::mem::take(&mut self.legacy_active) {
+            self.insert_active(MagicLinkKey { seed, msg_hash }, link);
+        }
+        for (seed, stats) in std::mem::take(&mut self.legacy_stats) {
+            self.stats.insert(seed, stats);
+        }
+        for ((seed, msg_hash), expiration) in std::mem::take(&mut self.legacy_used) {
+            self.insert_used(MagicLinkKey { seed, msg_hash }, expiration);
+        }
+        for ((seed, msg_hash), attempts) in std::mem::take(&mut self.legacy_incorrect_code_attempts)
+        {
+            self.incorrect_code_attempts
+                .insert(MagicLinkKey { seed, msg_hash }, attempts);
+        }
+
+        // Stats recorded before the principal index and running totals existed are added the
+        // first time this runs
+        if self.principal_seeds.is_empty() {
+            let seeds: Vec<_> = self.stats.iter().map(|(seed, _)| seed).collect();
+            for seed in seeds {
+                self.principal_seeds
+                    .insert(hash_bytes(principal_from_seed(seed).as_slice()), seed);
+            }
+        }
+        if self.totals.is_none() {
+            let mut totals = EmailTotals::default();
+            for (_, stats) in self.stats.iter() {
+                totals.emails_sent += stats.emails_sent as u64;
+                totals.successful_links += stats.successful_links as u64;
+            }
+            self.totals = Some(totals);
+        }
+    }
+
+    // Records that an email is about to be sent, unless the rate limit policy requires the
+    // address to back off, in which case the time remaining until it can be retried is returned.
+    // This is recorded before the email is sent so that concurrent calls can't bypass the limit,
+    // whereas `emails_sent` is only incremented once the email has been sent successfully.
+    pub fn record_email_attempt(
+        &mut self,
+        seed: Hash,
+        principal: Principal,
+        policy: &EmailRateLimitPolicy,
+        now: TimestampMillis,
+    ) -> Result<(), Milliseconds> {
+        match self.stats.get(&seed) {
+            Some(mut stats) => {
+                if let Some(blocked_until) = stats.blocked_until(policy, now) {
+                    return Err(blocked_until - now);
+                }
+                stats.recent_emails_sent = stats.recent_emails_sent(policy, now) + 1;
+                stats.latest_email_sent = now;
+                self.stats.insert(seed, stats);
+            }
+            None => {
+                self.principal_seeds
+                    .insert(hash_bytes(principal.as_slice()), seed);
+                self.stats.insert(
+                    seed,
+                    EmailStats {
+                        first_seen: now,
+                        emails_sent: 0,
+                        latest_email_sent: now,
+                        successful_links: 0,
+                        latest_successful_link: None,
+                        recent_emails_sent: 1,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
     pub fn mark_magic_link_sent(
         &mut self,
         seed: Hash,
         msg_hash: Hash,
         expiration: TimestampMillis,
+        link_expiration: TimestampMillis,
         now: TimestampMillis,
     ) {
         self.prune_expired(now);
-        self.active.insert((seed, msg_hash), expiration);
-        self.stats
-            .entry(seed)
-            .and_modify(|s| {
-                s.emails_sent += 1;
-                s.latest_email_sent = now;
-            })
-            .or_insert(EmailStats {
-                first_seen: now,
-                emails_sent: 1,
-                latest_email_sent: now,
-                successful_links: 0,
-                latest_successful_link: None,
-            });
-    }
-
-    pub fn mark_success(&mut self, seed: Hash, msg_hash: Hash, now: TimestampMillis) {
+        self.insert_active(
+            MagicLinkKey { seed, msg_hash },
+            ActiveMagicLink {
+                expiration,
+                link_expiration,
+            },
+        );
+        if let Some(mut stats) = self.stats.get(&seed) {
+            stats.emails_sent += 1;
+            self.stats.insert(seed, stats);
+            self.totals_mut().emails_sent += 1;
+        }
+    }
+
+    pub fn add_short_link(
+        &mut self,
+        signed_magic_link: SignedMagicLink,
+        expiration: TimestampMillis,
+    ) {
+        let Some(token_hash) = signed_magic_link.short_token.as_deref().map(hash_string) else {
+            return;
+        };
+        self.short_links.insert(
+            token_hash,
+            ShortMagicLink {
+                signed_magic_link,
+                expiration,
+            },
+        );
+        self.expirations.insert(
+            ExpiryKey {
+                expiration,
+                entry: ExpiringEntry::ShortLink(token_hash),
+            },
+            (),
+        );
+    }
+
+    pub fn resolve_short_link(&self, short_token: &str) -> Option<SignedMagicLink> {
+        self.short_links
+            .get(&hash_string(short_token))
+            .map(|link| link.signed_magic_link)
+    }
+
+    // Used links are only remembered until the link itself expires, after which it is rejected as
+    // expired before checking whether it has been used
+    pub fn mark_success(
+        &mut self,
+        seed: Hash,
+        msg_hash: Hash,
+        link_expiration: TimestampMillis,
+        now: TimestampMillis,
+    ) {
         self.prune_expired(now);
-        self.active.remove(&(seed, msg_hash));
-        if let Some(stats) = self.stats.get_mut(&seed) {
+        let key = MagicLinkKey { seed, msg_hash };
+        if self.active.remove(&key).is_some() {
+            self.incorrect_code_attempts.remove(&key);
+            self.insert_used(key, link_expiration);
+        }
+        if let Some(mut stats) = self.stats.get(&seed) {
             stats.successful_links += 1;
             stats.latest_successful_link = Some(now);
+            self.stats.insert(seed, stats);
+            self.totals_mut().successful_links += 1;
+        }
+    }
+
+    pub fn status(
+        &self,
+        seed: Hash,
+        msg_hash: Hash,
+        max_incorrect_code_attempts: u32,
+        now: TimestampMillis,
+    ) -> MagicLinkStatusResponse {
+        if self.is_used(seed, msg_hash) {
+            MagicLinkStatusResponse::Verified
+        } else if let Some(link) = self.active.get(&MagicLinkKey { seed, msg_hash }) {
+            // Locked links can never be verified, so from the client's perspective they have expired.
+            // Expired links are pruned lazily, after which they are reported as unknown.
+            if link.link_expiration < now
+                || self.is_locked(seed, msg_hash, max_incorrect_code_attempts)
+            {
+                MagicLinkStatusResponse::Expired
+            } else {
+                MagicLinkStatusResponse::Pending
+            }
+        } else {
+            MagicLinkStatusResponse::Unknown
         }
     }
 
+    pub fn stats(&self, seed: Hash) -> Option<EmailStats> {
+        self.stats.get(&seed)
+    }
+
+    pub fn seed_from_principal(&self, principal: Principal) -> Option<Hash> {
+        self.principal_seeds.get(&hash_bytes(principal.as_slice()))
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let totals = self.totals.unwrap_or_default();
+        let total_emails_sent = totals.emails_sent;
+        let total_successful_links = totals.successful_links;
+
+        Metrics {
+            total_emails_sent,
+            total_successful_links,
+            success_ratio: if total_emails_sent > 0 {
+                total_successful_links as f64 / total_emails_sent as f64
+            } else {
+                0.0
+            },
+            active_magic_links: self.active_len(),
+            distinct_users: self.stats.len() as u64,
+        }
+    }
+
+    pub fn active_len(&self) -> u64 {
+        self.active.len()
+    }
+
+    pub fn is_active(&self, seed: Hash, msg_hash: Hash) -> bool {
+        self.active.contains_key(&MagicLinkKey { seed, msg_hash })
+    }
+
+    pub fn is_used(&self, seed: Hash, msg_hash: Hash) -> bool {
+        self.used.contains_key(&MagicLinkKey { seed, msg_hash })
+    }
+
+    pub fn is_locked(&self, seed: Hash, msg_hash: Hash, max_incorrect_code_attempts: u32) -> bool {
+        self.incorrect_code_attempts
+            .get(&MagicLinkKey { seed, msg_hash })
+            .is_some_and(|attempts| attempts >= max_incorrect_code_attempts)
+    }
+
+    // Returns true if the link is now locked due to too many incorrect attempts
+    pub fn record_incorrect_code(
+        &mut self,
+        seed: Hash,
+        msg_hash: Hash,
+        max_incorrect_code_attempts: u32,
+        now: TimestampMillis,
+    ) -> bool {
+        self.prune_expired(now);
+        let key = MagicLinkKey { seed, msg_hash };
+        let attempts = self.incorrect_code_attempts.get(&key).unwrap_or_default() + 1;
+        self.incorrect_code_attempts.insert(key, attempts);
+        attempts >= max_incorrect_code_attempts
+    }
+
+    fn totals_mut(&mut self) -> &mut EmailTotals {
+        self.totals.get_or_insert_with(EmailTotals::default)
+    }
+
+    fn insert_active(&mut self, key: MagicLinkKey, link: ActiveMagicLink) {
+        self.expirations.insert(
+            ExpiryKey {
+                expiration: link.link_expiration,
+                entry: ExpiringEntry::Active(key),
+            },
+            (),
+        );
+        self.active.insert(key, link);
+    }
+
+    fn insert_used(&mut self, key: MagicLinkKey, expiration: TimestampMillis) {
+        self.expirations.insert(
+            ExpiryKey {
+                expiration,
+                entry: ExpiringEntry::Used(key),
+            },
+            (),
+        );
+        self.used.insert(key, expiration);
+    }
+
+    // Pops expired entries from the front of the expiry index. Entries which were removed early,
+    // such as active links which have since been used, may still be in the index, in which case
+    // removing them again is a no-op.
     fn prune_expired(&mut self, now: TimestampMillis) {
-        self.active.retain(|_, ts| *ts > now)
+        for _ in 0..MAX_PRUNED_PER_CALL {
+            let Some((expiry_key, _)) = self.expirations.iter().next() else {
+                break;
+            };
+            if expiry_key.expiration > now {
+                break;
+            }
+            self.expirations.remove(&expiry_key);
+
+            match expiry_key.entry {
+                ExpiringEntry::Active(key) => {
+                    self.active.remove(&key);
+                    self.incorrect_code_attempts.remove(&key);
+                }
+                ExpiringEntry::ShortLink(token_hash) => {
+                    self.short_links.remove(&token_hash);
+                }
+                ExpiringEntry::Used(key) => {
+                    self.used.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+fn init_active() -> StableBTreeMap<MagicLinkKey, ActiveMagicLink, Memory> {
+    StableBTreeMap::init(get_active_magic_links_memory())
+}
+
+fn init_stats() -> StableBTreeMap<Hash, EmailStats, Memory> {
+    StableBTreeMap::init(get_email_stats_memory())
+}
+
+fn init_principal_seeds() -> StableBTreeMap<Hash, Hash, Memory> {
+    StableBTreeMap::init(get_principal_seeds_memory())
+}
+
+fn init_short_links() -> StableBTreeMap<Hash, ShortMagicLink, Memory> {
+    StableBTreeMap::init(get_short_magic_links_memory())
+}
+
+fn init_used() -> StableBTreeMap<MagicLinkKey, TimestampMillis, Memory> {
+    StableBTreeMap::init(get_used_magic_links_memory())
+}
+
+fn init_incorrect_code_attempts() -> StableBTreeMap<MagicLinkKey, u32, Memory> {
+    StableBTreeMap::init(get_incorrect_code_attempts_memory())
+}
+
+fn init_expirations() -> StableBTreeMap<ExpiryKey, (), Memory> {
+    StableBTreeMap::init(get_magic_link_expirations_memory())
+}
+
+impl Storable for MagicLinkKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(&self.seed);
+        bytes.extend_from_slice(&self.msg_hash);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        MagicLinkKey {
+            seed: bytes[..32].try_into().unwrap(),
+            msg_hash: bytes[32..].try_into().unwrap(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 64,
+        is_fixed_size: true,
+    };
+}
+
+// Encoded as the expiration followed by a tag identifying the type of entry and then its key
+impl Storable for ExpiryKey {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = Vec::with_capacity(73);
+        bytes.extend_from_slice(&self.expiration.to_be_bytes());
+        match &self.entry {
+            ExpiringEntry::Active(key) => {
+                bytes.push(0);
+                bytes.extend_from_slice(&key.to_bytes());
+            }
+            ExpiringEntry::ShortLink(token_hash) => {
+                bytes.push(1);
+                bytes.extend_from_slice(token_hash);
+            }
+            ExpiringEntry::Used(key) => {
+                bytes.push(2);
+                bytes.extend_from_slice(&key.to_bytes());
+            }
+        }
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let expiration = TimestampMillis::from_be_bytes(bytes[..8].try_into().unwrap());
+        let key = &bytes[9..];
+        let entry = match bytes[8] {
+            0 => ExpiringEntry::Active(MagicLinkKey::from_bytes(Cow::Borrowed(key))),
+            1 => ExpiringEntry::ShortLink(key.try_into().unwrap()),
+            2 => ExpiringEntry::Used(MagicLinkKey::from_bytes(Cow::Borrowed(key))),
+            tag => panic!("Unexpected expiry key tag: {tag}"),
+        };
+        ExpiryKey { expiration, entry }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 73,
+        is_fixed_size: false,
+    };
+}
+
+impl Storable for ActiveMagicLink {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(rmp_serde::to_vec_named(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        rmp_serde::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for ShortMagicLink {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(rmp_serde::to_vec_named(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        rmp_serde::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for EmailStats {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(rmp_serde::to_vec_named(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        rmp_serde::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl From<&EmailStats> for sign_in_with_email_canister::EmailStats {
+    fn from(value: &EmailStats) -> Self {
+        sign_in_with_email_canister::EmailStats {
+            first_seen: value.first_seen,
+            emails_sent: value.emails_sent,
+            latest_email_sent: value.latest_email_sent,
+            successful_links: value.successful_links,
+            latest_successful_link: value.latest_successful_link,
+        }
+    }
+}
+
+impl EmailStats {
+    fn recent_emails_sent(&self, policy: &EmailRateLimitPolicy, now: TimestampMillis) -> u32 {
+        if now.saturating_sub(self.latest_email_sent) >= policy.reset_after {
+            0
+        } else {
+            self.recent_emails_sent
+        }
+    }
+
+    fn blocked_until(
+        &self,
+        policy: &EmailRateLimitPolicy,
+        now: TimestampMillis,
+    ) -> Option<TimestampMillis> {
+        let recent_emails_sent = self.recent_emails_sent(policy, now);
+        if recent_emails_sent < policy.free_emails {
+            return None;
+        }
+
+        let exponent = recent_emails_sent - policy.free_emails;
+        let backoff = policy
+            .initial_backoff
+            .saturating_mul(2u64.saturating_pow(exponent))
+            .min(policy.max_backoff);
+
+        let blocked_until = self.latest_email_sent.saturating_add(backoff);
+        (blocked_until > now).then_some(blocked_until)
     }
 }
@@ -1,2 +1,7 @@
+pub mod certified_http_responses;
+pub mod counters;
+pub mod email_sender_keys;
 pub mod magic_links;
 pub mod salt;
+pub mod signatures;
+pub mod throttler;
@@ -0,0 +1,67 @@
+use crate::Hash;
+use canister_sig_util::hash_bytes;
+use canister_sig_util::signature_map::SignatureMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sign_in_with_email_canister::TimestampNanos;
+use std::collections::BTreeMap;
+
+// The `SignatureMap` can't be serialized, so alongside it we keep each of the entries it holds,
+// which are serialized in its place and used to rebuild it after an upgrade.
+#[derive(Default)]
+pub struct Signatures {
+    map: SignatureMap,
+    // (hashed seed, msg hash) -> expiry
+    entries: BTreeMap<(Hash, Hash), TimestampNanos>,
+}
+
+impl Signatures {
+    pub fn add(&mut self, seed: Hash, msg_hash: Hash, expires_at: TimestampNanos) {
+        let hashed_seed = hash_bytes(seed);
+        self.map.put(hashed_seed, msg_hash, expires_at);
+        self.entries.insert((hashed_seed, msg_hash), expires_at);
+    }
+
+    // Returns true if any signatures were pruned from the map
+    pub fn prune_expired(&mut self, now: TimestampNanos) -> bool {
+        self.entries.retain(|_, expires_at| *expires_at > now);
+        self.map.prune_expired(now) > 0
+    }
+
+    pub fn get_signature_as_cbor(
+        &self,
+        seed: Hash,
+        msg_hash: Hash,
+        certified_assets_root_hash: Option<Hash>,
+    ) -> Option<Vec<u8>> {
+        self.map
+            .get_signature_as_cbor(&seed, msg_hash, certified_assets_root_hash)
+            .ok()
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        self.map.root_hash()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl Serialize for Signatures {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.entries.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Signatures {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = BTreeMap::<(Hash, Hash), TimestampNanos>::deserialize(deserializer)?;
+
+        let mut map = SignatureMap::default();
+        for (&(hashed_seed, msg_hash), &expires_at) in entries.iter() {
+            map.put(hashed_seed, msg_hash, expires_at);
+        }
+
+        Ok(Signatures { map, entries })
+    }
+}
@@ -0,0 +1,86 @@
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use sign_in_with_email_canister::{
+    Milliseconds, SlidingWindowLimit, ThrottlePolicy, TimestampMillis, ONE_MINUTE,
+};
+use std::collections::{HashMap, VecDeque};
+
+// Tracks the requests made by each caller, plus the emails sent across all callers, so that we can
+// cap the rate at which the canister makes HTTPS outcalls. All anonymous callers share one bucket.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Throttler {
+    callers: HashMap<Principal, VecDeque<TimestampMillis>>,
+    global: VecDeque<TimestampMillis>,
+}
+
+impl Throttler {
+    pub fn check(
+        &mut self,
+        caller: Principal,
+        policy: &ThrottlePolicy,
+        now: TimestampMillis,
+    ) -> Result<(), Milliseconds> {
+        self.prune(policy, now);
+
+        let caller_limit = limit_for_caller(caller, policy);
+        let caller_requests = self.callers.entry(caller).or_default();
+        if let Some(retry_after) = retry_after(caller_requests, caller_limit, now) {
+            return Err(retry_after);
+        }
+
+        let global_limit = SlidingWindowLimit {
+            max_requests: policy.global_emails_per_minute,
+            window: ONE_MINUTE,
+        };
+        if let Some(retry_after) = retry_after(&mut self.global, &global_limit, now) {
+            return Err(retry_after);
+        }
+
+        Ok(())
+    }
+
+    pub fn record(&mut self, caller: Principal, now: TimestampMillis) {
+        self.callers.entry(caller).or_default().push_back(now);
+        self.global.push_back(now);
+    }
+
+    fn prune(&mut self, policy: &ThrottlePolicy, now: TimestampMillis) {
+        self.callers.retain(|caller, requests| {
+            let window = limit_for_caller(*caller, policy).window;
+            requests
+                .back()
+                .is_some_and(|ts| ts.saturating_add(window) > now)
+        });
+    }
+}
+
+fn limit_for_caller(caller: Principal, policy: &ThrottlePolicy) -> &SlidingWindowLimit {
+    if caller == Principal::anonymous() {
+        &policy.anonymous
+    } else {
+        &policy.per_caller
+    }
+}
+
+fn retry_after(
+    requests: &mut VecDeque<TimestampMillis>,
+    limit: &SlidingWindowLimit,
+    now: TimestampMillis,
+) -> Option<Milliseconds> {
+    while requests
+        .front()
+        .is_some_and(|ts| ts.saturating_add(limit.window) <= now)
+    {
+        requests.pop_front();
+    }
+
+    if requests.len() < limit.max_requests as usize {
+        None
+    } else {
+        Some(
+            requests
+                .front()
+                .map_or(limit.window, |ts| ts + limit.window - now),
+        )
+    }
+}
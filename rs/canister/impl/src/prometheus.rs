@@ -0,0 +1,87 @@
+use crate::state::State;
+use crate::{env, memory};
+use std::fmt::{Display, Write};
+
+const PREFIX: &str = "sign_in_with_email";
+
+// Renders the canister's metrics in the Prometheus text exposition format
+pub fn encode_metrics(state: &State) -> String {
+    let mut output = String::new();
+    let counters = state.counters();
+
+    write_metric(
+        &mut output,
+        "cycles_balance",
+        "gauge",
+        "The canister's cycles balance",
+        env::cycles_balance(),
+    );
+    write_metric(
+        &mut output,
+        "heap_memory_bytes",
+        "gauge",
+        "The size of the canister's heap memory in bytes",
+        memory::heap_memory_size_bytes(),
+    );
+    write_metric(
+        &mut output,
+        "stable_memory_bytes",
+        "gauge",
+        "The size of the canister's stable memory in bytes",
+        memory::stable_memory_size_bytes(),
+    );
+    write_metric(
+        &mut output,
+        "signature_map_size",
+        "gauge",
+        "The number of signatures held in the signature map",
+        state.signature_map_len(),
+    );
+    write_metric(
+        &mut output,
+        "active_magic_links",
+        "gauge",
+        "The number of magic links which have been sent but not yet used",
+        state.active_magic_links(),
+    );
+    write_metric(
+        &mut output,
+        "emails_sent_total",
+        "counter",
+        "The number of emails sent successfully",
+        counters.emails_sent(),
+    );
+    write_metric(
+        &mut output,
+        "emails_failed_total",
+        "counter",
+        "The number of emails which failed to send",
+        counters.emails_failed(),
+    );
+
+    write_header(
+        &mut output,
+        "auth_results_total",
+        "counter",
+        "The number of magic link authentication attempts handled in update calls by result",
+    );
+    for (result, count) in counters.auth_results() {
+        writeln!(
+            output,
+            "{PREFIX}_auth_results_total{{result=\"{result}\"}} {count}"
+        )
+        .unwrap();
+    }
+
+    output
+}
+
+fn write_metric(output: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    write_header(output, name, kind, help);
+    writeln!(output, "{PREFIX}_{name} {value}").unwrap();
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(output, "# HELP {PREFIX}_{name} {help}").unwrap();
+    writeln!(output, "# TYPE {PREFIX}_{name} {kind}").unwrap();
+}
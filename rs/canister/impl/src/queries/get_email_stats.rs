@@ -0,0 +1,26 @@
+use crate::guards::caller_is_whitelisted;
+use crate::state;
+use ic_cdk::query;
+use sign_in_with_email_canister::{GetEmailStatsArgs, GetEmailStatsResponse};
+use utils::ValidatedEmail;
+
+#[query(guard = "caller_is_whitelisted")]
+fn get_email_stats(args: GetEmailStatsArgs) -> GetEmailStatsResponse {
+    state::read(|s| {
+        let seed = match args {
+            GetEmailStatsArgs::Email(email) => {
+                let Ok(email) = ValidatedEmail::try_from(email) else {
+                    return GetEmailStatsResponse::NotFound;
+                };
+                Some(s.calculate_seed(&email))
+            }
+            GetEmailStatsArgs::Principal(principal) => s.seed_from_principal(principal),
+        };
+
+        if let Some(stats) = seed.and_then(|seed| s.email_stats(seed)) {
+            GetEmailStatsResponse::Success(stats)
+        } else {
+            GetEmailStatsResponse::NotFound
+        }
+    })
+}
@@ -1,8 +1,11 @@
+use crate::http_responses::{
+    self, auth_response_key, code_entry_key, AUTH_PATH, METRICS_PATH, NOT_FOUND,
+};
 use crate::state::AuthResult;
-use crate::{env, get_query_param_value, state};
+use crate::translations::Language;
+use crate::{env, get_query_param_value, parse_magic_link, prometheus, state};
 use ic_cdk::{query, update};
 use ic_http_certification::{HttpRequest, HttpResponse};
-use magic_links::DoubleSignedMagicLink;
 
 #[query]
 fn http_request(request: HttpRequest) -> HttpResponse {
@@ -14,58 +17,79 @@ fn http_request_update(request: HttpRequest) -> HttpResponse {
     handle_http_request(request, true)
 }
 
+// Responses to update calls go through consensus so only query responses need to be certified
 fn handle_http_request(request: HttpRequest, update: bool) -> HttpResponse {
-    let Ok(path) = request.get_path() else {
-        return not_found();
-    };
+    let path = request.get_path().unwrap_or_default();
+    let language = Language::from_accept_language(
+        request
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("accept-language"))
+            .map(|(_, v)| v.as_str()),
+    );
 
     match path.as_str() {
-        "/auth" => {
-            let query = request.get_query().unwrap().unwrap_or_default();
+        AUTH_PATH => {
+            let query = request.get_query().ok().flatten().unwrap_or_default();
             let params = querystring::querify(&query);
-            let magic_link_hex = get_query_param_value(&params, "m").unwrap();
-            let signature1_hex = get_query_param_value(&params, "s1").unwrap();
-            let signature2_hex = get_query_param_value(&params, "s2").unwrap();
-            let code = get_query_param_value(&params, "c").unwrap();
-            let magic_link = DoubleSignedMagicLink::from_hex_strings(
-                &magic_link_hex,
-                &signature1_hex,
-                &signature2_hex,
-            );
-            let (status_code, body, upgrade) = match state::mutate(|s| {
-                s.process_auth_request(magic_link, code, update, env::now())
-            }) {
-                AuthResult::Success => (
-                    200,
-                    "Successfully signed in! You may now close this tab and return to OpenChat"
-                        .to_string(),
-                    false,
-                ),
-                AuthResult::RequiresUpgrade => (200, "".to_string(), true),
-                AuthResult::LinkExpired => (400, "Link expired".to_string(), false),
-                AuthResult::LinkInvalid(error) => (400, format!("Link invalid: {error}"), false),
-                AuthResult::CodeIncorrect => (400, "Code incorrect".to_string(), false),
+            let result = match parse_magic_link(&params) {
+                Ok(magic_link) => {
+                    // The code is either included in the link or submitted via the code entry form
+                    let code = if request.method.eq_ignore_ascii_case("POST") {
+                        let form = std::str::from_utf8(&request.body).unwrap_or_default();
+                        get_query_param_value(&querystring::querify(form), "c")
+                    } else {
+                        get_query_param_value(&params, "c")
+                    };
+                    let Some(code) = code else {
+                        return if update {
+                            state::read(|s| {
+                                http_responses::code_entry_response(s.branding(), language)
+                            })
+                        } else {
+                            state::read(|s| {
+                                s.certified_http_response(&code_entry_key(language), &path)
+                                    .unwrap()
+                            })
+                        };
+                    };
+                    state::mutate(|s| s.process_auth_request(magic_link, code, update, env::now()))
+                }
+                Err(error) => AuthResult::LinkInvalid(error),
             };
 
-            HttpResponse {
-                status_code,
-                headers: vec![
-                    ("content-type".to_string(), "text/plain".to_string()),
-                    ("content-length".to_string(), body.len().to_string()),
-                ],
-                body: body.into_bytes(),
-                upgrade: upgrade.then_some(true),
+            if update {
+                state::read(|s| http_responses::auth_response(&result, s.branding(), language))
+            } else {
+                // Results which don't have a certified response are upgraded to an update call
+                state::read(|s| {
+                    s.certified_http_response(&auth_response_key(&result, language), &path)
+                        .or_else(|| {
+                            s.certified_http_response(
+                                &auth_response_key(&AuthResult::RequiresUpgrade, language),
+                                &path,
+                            )
+                        })
+                        .unwrap()
+                })
             }
         }
-        _ => not_found(),
-    }
-}
+        METRICS_PATH => {
+            let response =
+                http_responses::metrics_response(state::read(prometheus::encode_metrics));
 
-fn not_found() -> HttpResponse {
-    HttpResponse {
-        status_code: 404,
-        headers: Vec::new(),
-        body: Vec::new(),
-        upgrade: None,
+            if update {
+                response
+            } else {
+                state::read(|s| s.skip_http_certification(METRICS_PATH, response, &path))
+            }
+        }
+        _ => {
+            if update {
+                http_responses::not_found()
+            } else {
+                state::read(|s| s.certified_http_response(NOT_FOUND, &path).unwrap())
+            }
+        }
     }
 }
@@ -0,0 +1,20 @@
+use crate::{env, state};
+use ic_cdk::query;
+use sign_in_with_email_canister::{Delegation, MagicLinkStatusArgs, MagicLinkStatusResponse};
+use utils::ValidatedEmail;
+
+#[query]
+fn magic_link_status(args: MagicLinkStatusArgs) -> MagicLinkStatusResponse {
+    let Ok(email) = ValidatedEmail::try_from(args.email) else {
+        return MagicLinkStatusResponse::Unknown;
+    };
+
+    state::read(|s| {
+        let seed = s.calculate_seed(&email);
+        let delegation = Delegation {
+            pubkey: args.session_key,
+            expiration: args.expiration,
+        };
+        s.magic_link_status(seed, &delegation, env::now())
+    })
+}
@@ -0,0 +1,9 @@
+use crate::guards::caller_is_whitelisted;
+use crate::state;
+use ic_cdk::query;
+use sign_in_with_email_canister::Metrics;
+
+#[query(guard = "caller_is_whitelisted")]
+fn metrics() -> Metrics {
+    state::read(|s| s.metrics())
+}
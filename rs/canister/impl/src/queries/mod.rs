@@ -1,5 +1,9 @@
 pub mod email_sender_config;
 pub mod get_delegation;
+pub mod get_email_stats;
 pub mod get_principal;
 pub mod http_request;
+pub mod magic_link_status;
+pub mod metrics;
 pub mod rsa_public_key;
+pub mod rsa_public_keys;
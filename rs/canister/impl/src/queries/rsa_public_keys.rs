@@ -0,0 +1,21 @@
+use crate::{env, state};
+use ic_cdk::query;
+use rsa::pkcs8::{EncodePublicKey, LineEnding};
+use sign_in_with_email_canister::{PreviousRsaPublicKey, RsaPublicKeysResponse};
+
+// Returns the current key along with any previous keys which links may still have been signed by
+#[query]
+fn rsa_public_keys() -> Option<RsaPublicKeysResponse> {
+    state::read(|s| {
+        s.rsa_public_key().map(|k| RsaPublicKeysResponse {
+            current: k.to_public_key_pem(LineEnding::LF).unwrap(),
+            previous: s
+                .previous_rsa_public_keys(env::now())
+                .map(|(public_key, valid_until)| PreviousRsaPublicKey {
+                    public_key_pem: public_key.to_public_key_pem(LineEnding::LF).unwrap(),
+                    valid_until,
+                })
+                .collect(),
+        })
+    })
+}
@@ -1,5 +1,5 @@
 use rand::rngs::StdRng;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rsa::RsaPrivateKey;
 use std::cell::RefCell;
 
@@ -20,6 +20,10 @@ pub fn generate_rsa_private_key() -> RsaPrivateKey {
     with_rng(|rng| RsaPrivateKey::new(rng, 2048).unwrap())
 }
 
+pub fn generate_ed25519_private_key() -> [u8; 32] {
+    with_rng(|rng| rng.gen())
+}
+
 pub fn with_rng<F: FnOnce(&mut StdRng) -> T, T>(f: F) -> T {
     RNG.with_borrow_mut(|rng| f(rng.as_mut().unwrap()))
 }
@@ -1,15 +1,27 @@
+use crate::magic_link_signer::MagicLinkSigner;
+use crate::model::certified_http_responses::CertifiedHttpResponses;
+use crate::model::counters::Counters;
+use crate::model::email_sender_keys::EmailSenderKeys;
 use crate::model::magic_links::MagicLinks;
 use crate::model::salt::Salt;
+use crate::model::signatures::Signatures;
+use crate::model::throttler::Throttler;
 use crate::{env, Hash};
 use candid::Principal;
-use canister_sig_util::signature_map::{SignatureMap, LABEL_SIG};
+use canister_sig_util::signature_map::LABEL_SIG;
 use canister_sig_util::CanisterSigPublicKey;
-use ic_cdk::api::set_certified_data;
-use magic_links::DoubleSignedMagicLink;
+use ic_cdk::api::{data_certificate, set_certified_data};
+use ic_certification::{fork_hash, labeled_hash};
+use ic_http_certification::HttpResponse;
+use magic_links::{DoubleSignedMagicLink, PublicKey, SignedMagicLink, SigningKey};
 use rsa::{RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
 use sign_in_with_email_canister::{
-    Delegation, EmailSenderConfig, SignedDelegation, TimestampMillis, NANOS_PER_MILLISECOND,
+    Branding, Delegation, EmailRateLimitPolicy, EmailSenderConfig, EmailStats,
+    GenerateMagicLinkResponse, MagicLinkExpiryPolicy, MagicLinkSignatureScheme,
+    MagicLinkStatusResponse, Metrics, Milliseconds, SignedDelegation, ThrottlePolicy,
+    TimestampMillis, VerificationCodeFormat, DEFAULT_MAX_INCORRECT_CODE_ATTEMPTS,
+    NANOS_PER_MILLISECOND, ONE_MINUTE,
 };
 use std::cell::RefCell;
 use utils::{calculate_seed, delegation_signature_msg_hash};
@@ -20,20 +32,77 @@ thread_local! {
 
 #[derive(Serialize, Deserialize)]
 pub struct State {
+    #[serde(default)]
+    signatures: Signatures,
+    // This is rebuilt during init and post_upgrade so doesn't need to be persisted
     #[serde(skip)]
-    signature_map: SignatureMap,
+    http_responses: CertifiedHttpResponses,
     email_sender_config: Option<EmailSenderConfig>,
-    email_sender_rsa_public_key: RsaPublicKey,
+    #[serde(
+        alias = "email_sender_public_key",
+        alias = "email_sender_rsa_public_key"
+    )]
+    email_sender_public_keys: EmailSenderKeys,
     magic_links: MagicLinks,
     rsa_private_key: Option<RsaPrivateKey>,
+    #[serde(default)]
+    previous_rsa_public_keys: Vec<PreviousRsaPublicKey>,
+    #[serde(default)]
+    ed25519_private_key: Option<[u8; 32]>,
+    #[serde(default)]
+    threshold_key: Option<ThresholdKey>,
+    #[serde(default)]
+    previous_threshold_public_keys: Vec<PreviousThresholdPublicKey>,
+    #[serde(default)]
+    magic_link_signature_scheme: MagicLinkSignatureScheme,
     salt: Salt,
     #[serde(default)]
     whitelisted_principals: Vec<Principal>,
+    #[serde(default)]
+    email_rate_limit_policy: EmailRateLimitPolicy,
+    #[serde(default)]
+    throttle_policy: ThrottlePolicy,
+    #[serde(default)]
+    throttler: Throttler,
+    #[serde(default)]
+    verification_code_format: VerificationCodeFormat,
+    #[serde(default = "default_max_incorrect_code_attempts")]
+    max_incorrect_code_attempts: u32,
+    #[serde(default)]
+    magic_link_expiry_policy: MagicLinkExpiryPolicy,
+    #[serde(default)]
+    counters: Counters,
+    #[serde(default)]
+    branding: Branding,
+    #[serde(default)]
+    short_magic_links: bool,
     test_mode: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+struct PreviousRsaPublicKey {
+    public_key: RsaPublicKey,
+    valid_until: TimestampMillis,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ThresholdKey {
+    key_name: String,
+    public_key: PublicKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PreviousThresholdPublicKey {
+    public_key: PublicKey,
+    valid_until: TimestampMillis,
+}
+
 const STATE_ALREADY_INITIALIZED: &str = "State has already been initialized";
 const STATE_NOT_INITIALIZED: &str = "State has not been initialized";
+const MIN_VERIFICATION_CODE_LENGTH: u8 = 3;
+const MAX_VERIFICATION_CODE_LENGTH: u8 = 16;
+// How long a signed delegation remains available to be fetched via `get_delegation`
+const SIGNATURE_TIME_TO_LIVE: Milliseconds = 10 * ONE_MINUTE;
 
 pub fn init(state: State) {
     STATE.with_borrow_mut(|s| {
@@ -58,25 +127,57 @@ pub fn take() -> State {
 }
 
 impl State {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        email_sender_public_key: RsaPublicKey,
+        email_sender_public_key: PublicKey,
         whitelisted_principals: Vec<Principal>,
+        email_rate_limit_policy: EmailRateLimitPolicy,
+        throttle_policy: ThrottlePolicy,
+        verification_code_format: VerificationCodeFormat,
+        max_incorrect_code_attempts: u32,
+        magic_link_expiry_policy: MagicLinkExpiryPolicy,
+        branding: Branding,
+        short_magic_links: bool,
+        magic_link_signature_scheme: MagicLinkSignatureScheme,
         test_mode: bool,
     ) -> State {
+        validate_verification_code_format(&verification_code_format);
+        validate_max_incorrect_code_attempts(max_incorrect_code_attempts);
+        validate_magic_link_expiry_policy(&magic_link_expiry_policy);
+
         State {
-            signature_map: SignatureMap::default(),
+            signatures: Signatures::default(),
+            http_responses: CertifiedHttpResponses::default(),
             email_sender_config: None,
-            email_sender_rsa_public_key: email_sender_public_key,
+            email_sender_public_keys: EmailSenderKeys::single(email_sender_public_key),
             magic_links: MagicLinks::default(),
             rsa_private_key: None,
+            previous_rsa_public_keys: Vec::new(),
+            ed25519_private_key: None,
+            threshold_key: None,
+            previous_threshold_public_keys: Vec::new(),
+            magic_link_signature_scheme,
             salt: Salt::default(),
             whitelisted_principals,
+            email_rate_limit_policy,
+            throttle_policy,
+            throttler: Throttler::default(),
+            verification_code_format,
+            max_incorrect_code_attempts,
+            magic_link_expiry_policy,
+            counters: Counters::default(),
+            branding,
+            short_magic_links,
             test_mode,
         }
     }
 
-    pub fn email_sender_rsa_public_key(&self) -> &RsaPublicKey {
-        &self.email_sender_rsa_public_key
+    pub fn email_sender_public_keys(&self) -> &EmailSenderKeys {
+        &self.email_sender_public_keys
+    }
+
+    pub fn set_email_sender_public_keys(&mut self, keys: EmailSenderKeys) {
+        self.email_sender_public_keys = keys;
     }
 
     pub fn email_sender_config(&self) -> Option<&EmailSenderConfig> {
@@ -99,6 +200,109 @@ impl State {
         self.rsa_private_key = Some(private_key);
     }
 
+    // The previous public key remains valid until every link which it may have signed has expired,
+    // which is at most the max time to live of a link from now
+    pub fn rotate_rsa_private_key(&mut self, private_key: RsaPrivateKey, now: TimestampMillis) {
+        self.previous_rsa_public_keys
+            .retain(|k| k.valid_until >= now);
+
+        if let Some(previous) = self.rsa_private_key.replace(private_key) {
+            self.previous_rsa_public_keys.push(PreviousRsaPublicKey {
+                public_key: previous.to_public_key(),
+                valid_until: now + self.magic_link_expiry_policy.max_time_to_live,
+            });
+        }
+    }
+
+    pub fn previous_rsa_public_keys(
+        &self,
+        now: TimestampMillis,
+    ) -> impl Iterator<Item = (&RsaPublicKey, TimestampMillis)> {
+        self.previous_rsa_public_keys
+            .iter()
+            .filter(move |k| k.valid_until >= now)
+            .map(|k| (&k.public_key, k.valid_until))
+    }
+
+    pub fn ed25519_private_key(&self) -> Option<[u8; 32]> {
+        self.ed25519_private_key
+    }
+
+    pub fn set_ed25519_private_key(&mut self, private_key: [u8; 32]) {
+        self.ed25519_private_key = Some(private_key);
+    }
+
+    // As with the RSA key, the previous threshold key remains valid until every link which it may
+    // have signed has expired
+    pub fn set_threshold_public_key(
+        &mut self,
+        key_name: String,
+        public_key: PublicKey,
+        now: TimestampMillis,
+    ) {
+        self.previous_threshold_public_keys
+            .retain(|k| k.valid_until >= now);
+
+        let previous = self.threshold_key.replace(ThresholdKey {
+            key_name,
+            public_key,
+        });
+        if let Some(previous) = previous {
+            self.previous_threshold_public_keys
+                .push(PreviousThresholdPublicKey {
+                    public_key: previous.public_key,
+                    valid_until: now + self.magic_link_expiry_policy.max_time_to_live,
+                });
+        }
+    }
+
+    pub fn set_magic_link_signature_scheme(&mut self, scheme: MagicLinkSignatureScheme) {
+        self.magic_link_signature_scheme = scheme;
+    }
+
+    pub fn magic_link_signer(&self) -> MagicLinkSigner {
+        match &self.magic_link_signature_scheme {
+            MagicLinkSignatureScheme::Rsa => {
+                MagicLinkSigner::Local(SigningKey::Rsa(self.rsa_private_key.clone().unwrap()))
+            }
+            MagicLinkSignatureScheme::Ed25519 => {
+                MagicLinkSigner::Local(SigningKey::Ed25519(self.ed25519_private_key.unwrap()))
+            }
+            MagicLinkSignatureScheme::ThresholdEd25519 { key_name } => MagicLinkSigner::Threshold {
+                key_name: key_name.clone(),
+                public_key_cached: self
+                    .threshold_key
+                    .as_ref()
+                    .is_some_and(|k| k.key_name == *key_name),
+            },
+        }
+    }
+
+    // Links are accepted if signed by any of the canister's keys, so that links signed before the
+    // signature scheme was changed remain valid
+    fn magic_link_public_keys(&self, now: TimestampMillis) -> Vec<PublicKey> {
+        let rsa_public_key = self.rsa_public_key().map(PublicKey::Rsa);
+        let ed25519_public_key = self
+            .ed25519_private_key
+            .map(|k| SigningKey::Ed25519(k).public_key());
+        let threshold_public_key = self.threshold_key.as_ref().map(|k| k.public_key.clone());
+
+        [rsa_public_key, ed25519_public_key, threshold_public_key]
+            .into_iter()
+            .flatten()
+            .chain(
+                self.previous_rsa_public_keys(now)
+                    .map(|(k, _)| PublicKey::Rsa(k.clone())),
+            )
+            .chain(
+                self.previous_threshold_public_keys
+                    .iter()
+                    .filter(|k| k.valid_until >= now)
+                    .map(|k| k.public_key.clone()),
+            )
+            .collect()
+    }
+
     pub fn salt(&self) -> [u8; 32] {
         self.salt.get()
     }
@@ -111,10 +315,75 @@ impl State {
         self.whitelisted_principals = principals;
     }
 
+    pub fn set_email_rate_limit_policy(&mut self, policy: EmailRateLimitPolicy) {
+        self.email_rate_limit_policy = policy;
+    }
+
+    pub fn set_throttle_policy(&mut self, policy: ThrottlePolicy) {
+        self.throttle_policy = policy;
+    }
+
+    pub fn verification_code_format(&self) -> &VerificationCodeFormat {
+        &self.verification_code_format
+    }
+
+    pub fn set_verification_code_format(&mut self, format: VerificationCodeFormat) {
+        validate_verification_code_format(&format);
+        self.verification_code_format = format;
+    }
+
+    pub fn set_max_incorrect_code_attempts(&mut self, max_attempts: u32) {
+        validate_max_incorrect_code_attempts(max_attempts);
+        self.max_incorrect_code_attempts = max_attempts;
+    }
+
+    pub fn magic_link_expiry_policy(&self) -> &MagicLinkExpiryPolicy {
+        &self.magic_link_expiry_policy
+    }
+
+    pub fn set_magic_link_expiry_policy(&mut self, policy: MagicLinkExpiryPolicy) {
+        validate_magic_link_expiry_policy(&policy);
+        self.magic_link_expiry_policy = policy;
+    }
+
+    // TODO: Remove this once the state has been migrated to stable memory
+    pub fn migrate_to_stable_memory(&mut self) {
+        self.magic_links
+            .migrate_to_stable_memory(|seed| principal_from_seed(env::canister_id(), seed));
+    }
+
+    pub fn branding(&self) -> &Branding {
+        &self.branding
+    }
+
+    pub fn set_branding(&mut self, branding: Branding) {
+        self.branding = branding;
+    }
+
+    pub fn short_magic_links(&self) -> bool {
+        self.short_magic_links
+    }
+
+    pub fn set_short_magic_links(&mut self, enabled: bool) {
+        self.short_magic_links = enabled;
+    }
+
     pub fn test_mode(&self) -> bool {
         self.test_mode
     }
 
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    pub fn signature_map_len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn record_email_failed(&mut self) {
+        self.counters.record_email_failed();
+    }
+
     pub fn process_auth_request(
         &mut self,
         signed_magic_link: DoubleSignedMagicLink,
@@ -122,35 +391,74 @@ impl State {
         is_update: bool,
         now: TimestampMillis,
     ) -> AuthResult {
-        if !signed_magic_link.verify_sigs(
-            self.rsa_public_key().unwrap(),
-            self.email_sender_rsa_public_key.clone(),
-        ) {
+        if is_update {
+            self.prune_expired_signatures(now);
+        }
+        let result = self.authenticate(signed_magic_link, code, is_update, now);
+        // Counts can only be recorded in update calls, so failures which are returned directly
+        // from the query path, such as expired or invalid links, are not included
+        if is_update {
+            self.counters.record_auth_result(result.label());
+        }
+        result
+    }
+
+    fn authenticate(
+        &mut self,
+        signed_magic_link: DoubleSignedMagicLink,
+        code: String,
+        is_update: bool,
+        now: TimestampMillis,
+    ) -> AuthResult {
+        let email_sender_public_keys = self.email_sender_public_keys.valid_keys(now);
+        if !self
+            .magic_link_public_keys(now)
+            .iter()
+            .any(|k| signed_magic_link.verify_sigs(k, &email_sender_public_keys))
+        {
             return AuthResult::LinkInvalid("Invalid signature".to_string());
         };
 
         let magic_link = signed_magic_link.magic_link;
+        let code = magic_links::normalize_code(magic_link.code(), &code);
+        let msg_hash = delegation_signature_msg_hash(magic_link.delegation());
+        let seed = self.calculate_seed(magic_link.email());
 
         if magic_link.expired(now) {
             return AuthResult::LinkExpired;
+        } else if self.magic_links.is_used(seed, msg_hash) {
+            return AuthResult::LinkAlreadyUsed;
+        } else if !self.magic_links.is_active(seed, msg_hash) {
+            return AuthResult::LinkInvalid("Link not found".to_string());
+        } else if self
+            .magic_links
+            .is_locked(seed, msg_hash, self.max_incorrect_code_attempts)
+        {
+            return AuthResult::TooManyAttempts;
         } else if magic_link.code() != code {
-            return AuthResult::CodeIncorrect;
+            // Incorrect attempts must be recorded, so they can only be handled in update calls
+            return if !is_update {
+                AuthResult::RequiresUpgrade
+            } else if self.magic_links.record_incorrect_code(
+                seed,
+                msg_hash,
+                self.max_incorrect_code_attempts,
+                now,
+            ) {
+                AuthResult::TooManyAttempts
+            } else {
+                AuthResult::CodeIncorrect
+            };
         }
 
-        let msg_hash = delegation_signature_msg_hash(magic_link.delegation());
-        let seed = self.calculate_seed(magic_link.email());
-
-        if self
-            .signature_map
-            .get_signature_as_cbor(&seed, msg_hash, None)
-            .is_ok()
-        {
-            AuthResult::Success
-        } else if !is_update {
+        if !is_update {
             AuthResult::RequiresUpgrade
         } else {
-            self.signature_map.add_signature(&seed, msg_hash);
-            self.magic_links.mark_success(seed, msg_hash, now);
+            let signature_expires_at = ((now + SIGNATURE_TIME_TO_LIVE) * NANOS_PER_MILLISECOND)
+                .min(magic_link.delegation().expiration);
+            self.signatures.add(seed, msg_hash, signature_expires_at);
+            self.magic_links
+                .mark_success(seed, msg_hash, magic_link.expiration(), now);
             self.update_root_hash();
 
             AuthResult::Success
@@ -160,28 +468,90 @@ impl State {
     pub fn get_delegation(&self, seed: Hash, delegation: Delegation) -> Option<SignedDelegation> {
         let msg_hash = delegation_signature_msg_hash(&delegation);
 
-        self.signature_map
-            .get_signature_as_cbor(&seed, msg_hash, None)
-            .ok()
+        self.signatures
+            .get_signature_as_cbor(seed, msg_hash, Some(self.http_responses.root_hash()))
             .map(|s| SignedDelegation {
                 delegation,
                 signature: s,
             })
     }
 
+    // Checks the caller's throttle limits and then the rate limit for the email address. If both
+    // are satisfied the attempt is recorded against each of them.
+    pub fn record_email_attempt(
+        &mut self,
+        caller: Principal,
+        seed: Hash,
+        now: TimestampMillis,
+    ) -> Result<(), GenerateMagicLinkResponse> {
+        self.prune_expired_signatures(now);
+
+        self.throttler
+            .check(caller, &self.throttle_policy, now)
+            .map_err(GenerateMagicLinkResponse::Throttled)?;
+
+        let principal = principal_from_seed(env::canister_id(), seed);
+        self.magic_links
+            .record_email_attempt(seed, principal, &self.email_rate_limit_policy, now)
+            .map_err(GenerateMagicLinkResponse::Blocked)?;
+
+        self.throttler.record(caller, now);
+        Ok(())
+    }
+
     pub fn record_magic_link_sent(
         &mut self,
         seed: Hash,
         delegation: &Delegation,
+        magic_link_expiration: TimestampMillis,
+        short_link: Option<SignedMagicLink>,
         now: TimestampMillis,
     ) {
         let msg_hash = delegation_signature_msg_hash(delegation);
+        let expiration = delegation.expiration / NANOS_PER_MILLISECOND;
+        self.counters.record_email_sent();
         self.magic_links.mark_magic_link_sent(
             seed,
             msg_hash,
-            delegation.expiration / NANOS_PER_MILLISECOND,
+            expiration,
+            magic_link_expiration,
             now,
         );
+        if let Some(short_link) = short_link {
+            self.magic_links
+                .add_short_link(short_link, magic_link_expiration);
+        }
+    }
+
+    pub fn resolve_short_link(&self, short_token: &str) -> Option<SignedMagicLink> {
+        self.magic_links.resolve_short_link(short_token)
+    }
+
+    pub fn magic_link_status(
+        &self,
+        seed: Hash,
+        delegation: &Delegation,
+        now: TimestampMillis,
+    ) -> MagicLinkStatusResponse {
+        let msg_hash = delegation_signature_msg_hash(delegation);
+        self.magic_links
+            .status(seed, msg_hash, self.max_incorrect_code_attempts, now)
+    }
+
+    pub fn email_stats(&self, seed: Hash) -> Option<EmailStats> {
+        self.magic_links.stats(seed).map(|s| (&s).into())
+    }
+
+    pub fn seed_from_principal(&self, principal: Principal) -> Option<Hash> {
+        self.magic_links.seed_from_principal(principal)
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.magic_links.metrics()
+    }
+
+    pub fn active_magic_links(&self) -> u64 {
+        self.magic_links.active_len()
     }
 
     pub fn calculate_seed(&self, email: &str) -> Hash {
@@ -189,8 +559,7 @@ impl State {
     }
 
     pub fn der_encode_canister_sig_key(&self, seed: Hash) -> Vec<u8> {
-        let canister_id = env::canister_id();
-        CanisterSigPublicKey::new(canister_id, seed.to_vec()).to_der()
+        der_encode_canister_sig_key(env::canister_id(), seed)
     }
 
     pub fn is_caller_whitelisted(&self) -> bool {
@@ -198,17 +567,111 @@ impl State {
         self.whitelisted_principals.contains(&caller)
     }
 
-    fn update_root_hash(&mut self) {
-        let prefixed_root_hash =
-            ic_certification::labeled_hash(LABEL_SIG, &self.signature_map.root_hash());
-        set_certified_data(&prefixed_root_hash[..]);
+    fn prune_expired_signatures(&mut self, now: TimestampMillis) {
+        if self.signatures.prune_expired(now * NANOS_PER_MILLISECOND) {
+            self.update_root_hash();
+        }
+    }
+
+    pub fn set_http_responses(&mut self, http_responses: CertifiedHttpResponses) {
+        self.http_responses = http_responses;
+        self.update_root_hash();
+    }
+
+    pub fn certified_http_response(&self, key: &str, request_path: &str) -> Option<HttpResponse> {
+        self.http_responses.get(
+            key,
+            request_path,
+            self.signatures_root_hash(),
+            data_certificate().as_deref(),
+        )
+    }
+
+    pub fn skip_http_certification(
+        &self,
+        key: &str,
+        response: HttpResponse,
+        request_path: &str,
+    ) -> HttpResponse {
+        self.http_responses.skip_certification(
+            key,
+            response,
+            request_path,
+            self.signatures_root_hash(),
+            data_certificate().as_deref(),
+        )
+    }
+
+    // The certified data is made up of the HTTP certification tree on the left and the
+    // signatures on the right, matching the layout expected by `get_signature_as_cbor`
+    fn update_root_hash(&self) {
+        let root_hash = fork_hash(
+            &self.http_responses.root_hash(),
+            &self.signatures_root_hash(),
+        );
+        set_certified_data(&root_hash[..]);
+    }
+
+    fn signatures_root_hash(&self) -> Hash {
+        labeled_hash(LABEL_SIG, &self.signatures.root_hash())
     }
 }
 
+fn der_encode_canister_sig_key(canister_id: Principal, seed: Hash) -> Vec<u8> {
+    CanisterSigPublicKey::new(canister_id, seed.to_vec()).to_der()
+}
+
+// The principal which a user is given when they sign in using the delegation for this seed
+fn principal_from_seed(canister_id: Principal, seed: Hash) -> Principal {
+    Principal::self_authenticating(der_encode_canister_sig_key(canister_id, seed))
+}
+
+fn validate_verification_code_format(format: &VerificationCodeFormat) {
+    assert!(
+        (MIN_VERIFICATION_CODE_LENGTH..=MAX_VERIFICATION_CODE_LENGTH).contains(&format.length),
+        "Verification code length must be between {MIN_VERIFICATION_CODE_LENGTH} and {MAX_VERIFICATION_CODE_LENGTH}"
+    );
+}
+
+fn validate_max_incorrect_code_attempts(max_attempts: u32) {
+    assert!(
+        max_attempts > 0,
+        "Max incorrect code attempts must be greater than 0"
+    );
+}
+
+fn default_max_incorrect_code_attempts() -> u32 {
+    DEFAULT_MAX_INCORRECT_CODE_ATTEMPTS
+}
+
+fn validate_magic_link_expiry_policy(policy: &MagicLinkExpiryPolicy) {
+    assert!(
+        policy.min_time_to_live <= policy.default_time_to_live
+            && policy.default_time_to_live <= policy.max_time_to_live,
+        "Magic link expiry policy must satisfy min <= default <= max"
+    );
+}
+
 pub enum AuthResult {
     Success,
     RequiresUpgrade,
     LinkExpired,
+    LinkAlreadyUsed,
     CodeIncorrect,
+    TooManyAttempts,
     LinkInvalid(String),
 }
+
+impl AuthResult {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AuthResult::Success => "success",
+            AuthResult::RequiresUpgrade => "requires_upgrade",
+            AuthResult::LinkExpired => "link_expired",
+            AuthResult::LinkAlreadyUsed => "link_already_used",
+            AuthResult::CodeIncorrect => "code_incorrect",
+            AuthResult::TooManyAttempts => "too_many_attempts",
+            AuthResult::LinkInvalid(_) => "link_invalid",
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// The text shown on the pages served from `/auth`. Where `{app}` appears it is replaced with the
+// configured app name, or `app_fallback` if none is set.
+pub struct Translations {
+    pub app_fallback: &'static str,
+    pub success_title: &'static str,
+    pub success_message: &'static str,
+    pub return_to_app: &'static str,
+    pub code_entry_title: &'static str,
+    pub code_entry_message: &'static str,
+    pub code_entry_submit: &'static str,
+    pub link_expired_title: &'static str,
+    pub link_expired_message: &'static str,
+    pub link_already_used_title: &'static str,
+    pub link_already_used_message: &'static str,
+    pub link_invalid_title: &'static str,
+    pub link_invalid_message: &'static str,
+    pub code_incorrect_title: &'static str,
+    pub code_incorrect_message: &'static str,
+    pub too_many_attempts_title: &'static str,
+    pub too_many_attempts_message: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    Spanish,
+    French,
+    German,
+}
+
+impl Language {
+    pub const ALL: [Language; 4] = [
+        Language::English,
+        Language::Spanish,
+        Language::French,
+        Language::German,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Spanish => "es",
+            Language::French => "fr",
+            Language::German => "de",
+        }
+    }
+
+    pub fn translations(&self) -> &'static Translations {
+        match self {
+            Language::English => &ENGLISH,
+            Language::Spanish => &SPANISH,
+            Language::French => &FRENCH,
+            Language::German => &GERMAN,
+        }
+    }
+
+    // Picks the supported language with the highest weight from an `Accept-Language` header,
+    // falling back to English
+    pub fn from_accept_language(header: Option<&str>) -> Language {
+        let mut best: Option<(Language, f32)> = None;
+
+        for entry in header.unwrap_or_default().split(',') {
+            let mut parts = entry.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let weight = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let primary = tag.split('-').next().unwrap_or_default();
+            let Some(language) = Language::ALL
+                .into_iter()
+                .find(|l| l.code().eq_ignore_ascii_case(primary))
+            else {
+                continue;
+            };
+
+            if weight > 0.0 && best.map_or(true, |(_, w)| weight > w) {
+                best = Some((language, weight));
+            }
+        }
+
+        best.map(|(language, _)| language).unwrap_or_default()
+    }
+}
+
+const ENGLISH: Translations = Translations {
+    app_fallback: "the app",
+    success_title: "Signed in",
+    success_message: "You have successfully signed in! You may now close this tab and return to {app}.",
+    return_to_app: "Return to {app}",
+    code_entry_title: "Enter your code",
+    code_entry_message: "Enter the code shown in {app} to finish signing in.",
+    code_entry_submit: "Sign in",
+    link_expired_title: "Link expired",
+    link_expired_message: "This link has expired. Please request a new one from {app}.",
+    link_already_used_title: "Link already used",
+    link_already_used_message: "This link has already been used. If you need to sign in again, please request a new one from {app}.",
+    link_invalid_title: "Link invalid",
+    link_invalid_message: "This link is invalid.",
+    code_incorrect_title: "Code incorrect",
+    code_incorrect_message: "The code is incorrect. Please check the code shown in {app} and try again.",
+    too_many_attempts_title: "Too many attempts",
+    too_many_attempts_message: "Too many incorrect codes have been entered. Please request a new link from {app}.",
+};
+
+const SPANISH: Translations = Translations {
+    app_fallback: "la aplicación",
+    success_title: "Sesión iniciada",
+    success_message: "¡Has iniciado sesión correctamente! Ya puedes cerrar esta pestaña y volver a {app}.",
+    return_to_app: "Volver a {app}",
+    code_entry_title: "Introduce tu código",
+    code_entry_message: "Introduce el código que aparece en {app} para terminar de iniciar sesión.",
+    code_entry_submit: "Iniciar sesión",
+    link_expired_title: "Enlace caducado",
+    link_expired_message: "Este enlace ha caducado. Solicita uno nuevo desde {app}.",
+    link_already_used_title: "Enlace ya utilizado",
+    link_already_used_message: "Este enlace ya se ha utilizado. Si necesitas volver a iniciar sesión, solicita uno nuevo desde {app}.",
+    link_invalid_title: "Enlace no válido",
+    link_invalid_message: "Este enlace no es válido.",
+    code_incorrect_title: "Código incorrecto",
+    code_incorrect_message: "El código es incorrecto. Comprueba el código que aparece en {app} e inténtalo de nuevo.",
+    too_many_attempts_title: "Demasiados intentos",
+    too_many_attempts_message: "Se han introducido demasiados códigos incorrectos. Solicita un nuevo enlace desde {app}.",
+};
+
+const FRENCH: Translations = Translations {
+    app_fallback: "l'application",
+    success_title: "Connexion réussie",
+    success_message: "Vous êtes connecté ! Vous pouvez maintenant fermer cet onglet et revenir à {app}.",
+    return_to_app: "Revenir à {app}",
+    code_entry_title: "Saisissez votre code",
+    code_entry_message: "Saisissez le code affiché dans {app} pour terminer la connexion.",
+    code_entry_submit: "Se connecter",
+    link_expired_title: "Lien expiré",
+    link_expired_message: "Ce lien a expiré. Veuillez en demander un nouveau depuis {app}.",
+    link_already_used_title: "Lien déjà utilisé",
+    link_already_used_message: "Ce lien a déjà été utilisé. Si vous devez vous reconnecter, veuillez en demander un nouveau depuis {app}.",
+    link_invalid_title: "Lien invalide",
+    link_invalid_message: "Ce lien est invalide.",
+    code_incorrect_title: "Code incorrect",
+    code_incorrect_message: "Le code est incorrect. Veuillez vérifier le code affiché dans {app} et réessayer.",
+    too_many_attempts_title: "Trop de tentatives",
+    too_many_attempts_message: "Trop de codes incorrects ont été saisis. Veuillez demander un nouveau lien depuis {app}.",
+};
+
+const GERMAN: Translations = Translations {
+    app_fallback: "der App",
+    success_title: "Angemeldet",
+    success_message: "Du hast dich erfolgreich angemeldet! Du kannst diesen Tab jetzt schließen und zu {app} zurückkehren.",
+    return_to_app: "Zurück zu {app}",
+    code_entry_title: "Code eingeben",
+    code_entry_message: "Gib den in {app} angezeigten Code ein, um die Anmeldung abzuschließen.",
+    code_entry_submit: "Anmelden",
+    link_expired_title: "Link abgelaufen",
+    link_expired_message: "Dieser Link ist abgelaufen. Bitte fordere in {app} einen neuen an.",
+    link_already_used_title: "Link bereits verwendet",
+    link_already_used_message: "Dieser Link wurde bereits verwendet. Wenn du dich erneut anmelden musst, fordere bitte in {app} einen neuen an.",
+    link_invalid_title: "Link ungültig",
+    link_invalid_message: "Dieser Link ist ungültig.",
+    code_incorrect_title: "Code falsch",
+    code_incorrect_message: "Der Code ist falsch. Bitte überprüfe den in {app} angezeigten Code und versuche es erneut.",
+    too_many_attempts_title: "Zu viele Versuche",
+    too_many_attempts_message: "Es wurden zu viele falsche Codes eingegeben. Bitte fordere in {app} einen neuen Link an.",
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(None, Language::English)]
+    #[test_case(Some("fr"), Language::French)]
+    #[test_case(Some("de-DE,de;q=0.9,en;q=0.8"), Language::German)]
+    #[test_case(Some("en;q=0.5, es-ES;q=0.8"), Language::Spanish)]
+    #[test_case(Some("ja,fr;q=0.3"), Language::French)]
+    #[test_case(Some("ja, zh-CN"), Language::English)]
+    #[test_case(Some("es;q=0"), Language::English)]
+    fn select_language_from_accept_language(header: Option<&str>, expected: Language) {
+        assert_eq!(Language::from_accept_language(header), expected);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{email_sender, env, rng, state};
+use crate::{email_sender, env, magic_link_signer, rng, state};
 use ic_cdk::update;
 use sign_in_with_email_canister::{
     GenerateMagicLinkArgs, GenerateMagicLinkResponse, GenerateMagicLinkResponse::*,
@@ -12,37 +12,74 @@ async fn generate_magic_link(args: GenerateMagicLinkArgs) -> GenerateMagicLinkRe
         return EmailInvalid;
     };
 
+    let caller = env::caller();
     let start = env::now();
 
-    let (signed_magic_link, seed) = state::read(|s| {
+    let prepare_result = state::mutate(|s| {
         let seed = s.calculate_seed(email.as_str());
-        let magic_link = rng::with_rng(|rng| {
-            magic_links::generate(
-                email.to_string(),
-                args.session_key,
-                args.max_time_to_live,
-                rng,
-                start,
-            )
-        });
-        let rsa_private_key = s.rsa_private_key().unwrap();
-        (magic_link.sign(rsa_private_key), seed)
+        s.record_email_attempt(caller, seed, start).map(|_| {
+            let magic_link_time_to_live = s
+                .magic_link_expiry_policy()
+                .time_to_live(args.magic_link_time_to_live);
+            let magic_link = rng::with_rng(|rng| {
+                magic_links::generate(
+                    email.to_string(),
+                    args.session_key,
+                    args.max_time_to_live,
+                    magic_link_time_to_live,
+                    s.verification_code_format(),
+                    rng,
+                    start,
+                )
+            });
+            let short_token = s
+                .short_magic_links()
+                .then(|| rng::with_rng(magic_links::generate_short_token));
+            (magic_link, s.magic_link_signer(), short_token, seed)
+        })
     });
 
+    let (magic_link, signer, short_token, seed) = match prepare_result {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+
+    let mut signed_magic_link = match magic_link_signer::sign(signer, magic_link).await {
+        Ok(signed) => signed,
+        Err(error) => return FailedToSignMagicLink(error),
+    };
+    if let Some(short_token) = short_token {
+        signed_magic_link = signed_magic_link.with_short_token(short_token);
+    }
+
     let delegation = signed_magic_link.magic_link.delegation().clone();
     let code = signed_magic_link.magic_link.code().to_string();
+    let magic_link_expiration = signed_magic_link.magic_link.expiration();
+    // Stored once the email has been sent so that the canister can resolve the short token
+    let short_link = signed_magic_link
+        .short_token
+        .is_some()
+        .then(|| signed_magic_link.clone());
 
     if let Err(error) = email_sender::send_magic_link(signed_magic_link).await {
+        state::mutate(|s| s.record_email_failed());
         FailedToSendEmail(error)
     } else {
         state::mutate(|s| {
-            s.record_magic_link_sent(seed, &delegation, env::now());
+            s.record_magic_link_sent(
+                seed,
+                &delegation,
+                magic_link_expiration,
+                short_link,
+                env::now(),
+            );
 
             Success(GenerateMagicLinkSuccess {
                 created: start,
                 user_key: s.der_encode_canister_sig_key(seed),
                 expiration: delegation.expiration,
                 code,
+                magic_link_expiration,
             })
         })
     }
@@ -1,26 +1,28 @@
 use crate::{
-    env, get_query_param_value,
+    env, get_query_param_value, parse_magic_link,
     state::{self, AuthResult},
 };
 use ic_cdk::update;
-use magic_links::DoubleSignedMagicLink;
 use sign_in_with_email_canister::{HandleMagicLinkArgs, HandleMagicLinkResponse};
 
 #[update]
 async fn handle_magic_link(args: HandleMagicLinkArgs) -> HandleMagicLinkResponse {
     let params = querystring::querify(&args.link);
-    let magic_link_hex = get_query_param_value(&params, "m").unwrap();
-    let signature1_hex = get_query_param_value(&params, "s1").unwrap();
-    let signature2_hex = get_query_param_value(&params, "s2").unwrap();
-    let code = get_query_param_value(&params, "c").unwrap();
-    let magic_link =
-        DoubleSignedMagicLink::from_hex_strings(&magic_link_hex, &signature1_hex, &signature2_hex);
+    let magic_link = match parse_magic_link(&params) {
+        Ok(magic_link) => magic_link,
+        Err(error) => return HandleMagicLinkResponse::LinkInvalid(error),
+    };
+    let Some(code) = get_query_param_value(&params, "c") else {
+        return HandleMagicLinkResponse::LinkInvalid("Parameter 'c' missing".to_string());
+    };
 
     match state::mutate(|s| s.process_auth_request(magic_link, code, true, env::now())) {
         AuthResult::Success => HandleMagicLinkResponse::Success,
         AuthResult::LinkExpired => HandleMagicLinkResponse::LinkExpired,
+        AuthResult::LinkAlreadyUsed => HandleMagicLinkResponse::LinkAlreadyUsed,
         AuthResult::LinkInvalid(error) => HandleMagicLinkResponse::LinkInvalid(error),
         AuthResult::RequiresUpgrade => unreachable!(),
         AuthResult::CodeIncorrect => HandleMagicLinkResponse::CodeIncorrect,
+        AuthResult::TooManyAttempts => HandleMagicLinkResponse::TooManyAttempts,
     }
 }
@@ -1,2 +1,5 @@
 pub mod generate_magic_link;
 pub mod handle_magic_link;
+pub mod rotate_rsa_key;
+pub mod set_email_sender_config;
+pub mod set_email_sender_public_keys;
@@ -0,0 +1,24 @@
+use crate::guards::caller_is_controller;
+use crate::{env, rng, state};
+use ic_cdk::update;
+use rsa::pkcs8::{EncodePublicKey, LineEnding};
+use sign_in_with_email_canister::RotateRsaKeyResponse;
+
+// The email sender config is held decrypted in the state, so it doesn't need re-encrypting. Any
+// subsequent upgrade must encrypt the config using the new key returned by `rsa_public_key`.
+#[update(guard = "caller_is_controller")]
+fn rotate_rsa_key() -> RotateRsaKeyResponse {
+    if state::read(|s| s.rsa_private_key().is_none()) {
+        return RotateRsaKeyResponse::KeyNotInitialized;
+    }
+
+    let rsa_private_key = rng::generate_rsa_private_key();
+    let rsa_public_key_pem = rsa_private_key
+        .to_public_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+
+    state::mutate(|s| s.rotate_rsa_private_key(rsa_private_key, env::now()));
+
+    RotateRsaKeyResponse::Success(rsa_public_key_pem)
+}
@@ -0,0 +1,21 @@
+use crate::guards::caller_is_controller;
+use crate::{email_sender, state};
+use ic_cdk::update;
+use sign_in_with_email_canister::{EncryptedEmailSenderConfig, SetEmailSenderConfigResponse};
+
+// Swaps the email sender without needing to upgrade the canister
+#[update(guard = "caller_is_controller")]
+fn set_email_sender_config(config: EncryptedEmailSenderConfig) -> SetEmailSenderConfigResponse {
+    let Some(rsa_private_key) = state::read(|s| s.rsa_private_key()) else {
+        return SetEmailSenderConfigResponse::KeyNotInitialized;
+    };
+
+    match config.decrypt(&rsa_private_key) {
+        Ok(config) => {
+            state::mutate(|s| s.set_email_sender_config(config.clone()));
+            email_sender::init_from_config(config);
+            SetEmailSenderConfigResponse::Success
+        }
+        Err(error) => SetEmailSenderConfigResponse::DecryptionFailed(error),
+    }
+}
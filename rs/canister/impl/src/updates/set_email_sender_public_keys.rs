@@ -0,0 +1,24 @@
+use crate::guards::caller_is_controller;
+use crate::model::email_sender_keys::EmailSenderKeys;
+use crate::state;
+use ic_cdk::update;
+use sign_in_with_email_canister::{SetEmailSenderPublicKeysArgs, SetEmailSenderPublicKeysResponse};
+
+// Allows the email sender's key to be rotated by first adding the new key, then once the email
+// sender has switched over, removing the old key or giving it an expiry
+#[update(guard = "caller_is_controller")]
+fn set_email_sender_public_keys(
+    args: SetEmailSenderPublicKeysArgs,
+) -> SetEmailSenderPublicKeysResponse {
+    if args.keys.is_empty() {
+        return SetEmailSenderPublicKeysResponse::NoKeys;
+    }
+
+    match EmailSenderKeys::try_from(args.keys) {
+        Ok(keys) => {
+            state::mutate(|s| s.set_email_sender_public_keys(keys));
+            SetEmailSenderPublicKeysResponse::Success
+        }
+        Err(error) => SetEmailSenderPublicKeysResponse::InvalidPublicKey(error),
+    }
+}
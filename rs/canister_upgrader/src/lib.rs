@@ -7,7 +7,10 @@ use ic_utils::interfaces::ManagementCanister;
 use rand::thread_rng;
 use rsa::pkcs8::DecodePublicKey;
 use rsa::RsaPublicKey;
-use sign_in_with_email_canister::{EmailSenderConfig, InitOrUpgradeArgs, UpgradeArgs};
+use sign_in_with_email_canister::{
+    EmailSenderConfig, EncryptedEmailSenderConfig, InitOrUpgradeArgs, SetEmailSenderConfigResponse,
+    UpgradeArgs,
+};
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
@@ -21,19 +24,8 @@ pub async fn upgrade_canister(
 ) {
     let agent = build_ic_agent(ic_url, identity).await;
 
-    let rsa_public_key_pem: Option<String> = candid::decode_one(
-        &agent
-            .query(&canister_id, "rsa_public_key")
-            .with_arg(candid::encode_one(()).unwrap())
-            .call()
-            .await
-            .unwrap(),
-    )
-    .unwrap();
-
-    let rsa_public_key = RsaPublicKey::from_public_key_pem(&rsa_public_key_pem.unwrap()).unwrap();
-
-    let encrypted_config = email_sender_config.encrypt(&rsa_public_key, &mut thread_rng());
+    let encrypted_config =
+        encrypt_email_sender_config(&agent, canister_id, email_sender_config).await;
 
     let management_canister = ManagementCanister::create(&agent);
     let wasm = read_canister_wasm();
@@ -43,6 +35,7 @@ pub async fn upgrade_canister(
         .with_arg(InitOrUpgradeArgs::Upgrade(UpgradeArgs {
             email_sender_public_key_pem,
             email_sender_config: Some(encrypted_config),
+            ..Default::default()
         }))
         .with_mode(InstallMode::Upgrade(None))
         .call_and_wait()
@@ -50,6 +43,52 @@ pub async fn upgrade_canister(
         .unwrap();
 }
 
+pub async fn set_email_sender_config(
+    identity: Box<dyn Identity>,
+    ic_url: String,
+    canister_id: Principal,
+    email_sender_config: EmailSenderConfig,
+) {
+    let agent = build_ic_agent(ic_url, identity).await;
+
+    let encrypted_config =
+        encrypt_email_sender_config(&agent, canister_id, email_sender_config).await;
+
+    let response: SetEmailSenderConfigResponse = candid::decode_one(
+        &agent
+            .update(&canister_id, "set_email_sender_config")
+            .with_arg(candid::encode_one(encrypted_config).unwrap())
+            .call_and_wait()
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+
+    if !matches!(response, SetEmailSenderConfigResponse::Success) {
+        panic!("Failed to set email sender config: {response:?}");
+    }
+}
+
+async fn encrypt_email_sender_config(
+    agent: &Agent,
+    canister_id: Principal,
+    email_sender_config: EmailSenderConfig,
+) -> EncryptedEmailSenderConfig {
+    let rsa_public_key_pem: Option<String> = candid::decode_one(
+        &agent
+            .query(&canister_id, "rsa_public_key")
+            .with_arg(candid::encode_one(()).unwrap())
+            .call()
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+
+    let rsa_public_key = RsaPublicKey::from_public_key_pem(&rsa_public_key_pem.unwrap()).unwrap();
+
+    email_sender_config.encrypt(&rsa_public_key, &mut thread_rng())
+}
+
 async fn build_ic_agent(url: String, identity: Box<dyn Identity>) -> Agent {
     let mainnet = is_mainnet(&url);
     let transport = ReqwestTransport::create(url).expect("Failed to create Reqwest transport");
@@ -1,6 +1,6 @@
 use candid::Principal;
-use canister_upgrader::{get_dfx_identity, upgrade_canister};
-use clap::Parser;
+use canister_upgrader::{get_dfx_identity, set_email_sender_config, upgrade_canister};
+use clap::{Parser, Subcommand};
 use sign_in_with_email_canister::{AwsEmailSenderConfig, EmailSenderConfig};
 
 #[tokio::main]
@@ -8,20 +8,29 @@ async fn main() {
     let opts = Opts::parse();
 
     let identity = get_dfx_identity(&opts.identity);
+    let email_sender_config = EmailSenderConfig::Aws(AwsEmailSenderConfig {
+        region: opts.aws_region,
+        function_url: opts.aws_function_url,
+        access_key: opts.aws_access_key,
+        secret_key: opts.aws_secret_key,
+    });
 
-    upgrade_canister(
-        identity,
-        opts.ic_url,
-        opts.canister_id,
-        None,
-        EmailSenderConfig::Aws(AwsEmailSenderConfig {
-            region: opts.aws_region,
-            function_url: opts.aws_function_url,
-            access_key: opts.aws_access_key,
-            secret_key: opts.aws_secret_key,
-        }),
-    )
-    .await;
+    match opts.command.unwrap_or(Command::Upgrade) {
+        Command::Upgrade => {
+            upgrade_canister(
+                identity,
+                opts.ic_url,
+                opts.canister_id,
+                None,
+                email_sender_config,
+            )
+            .await
+        }
+        Command::SetEmailSenderConfig => {
+            set_email_sender_config(identity, opts.ic_url, opts.canister_id, email_sender_config)
+                .await
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -46,4 +55,15 @@ struct Opts {
 
     #[arg(long)]
     aws_secret_key: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upgrade the canister, passing in the email sender config (the default)
+    Upgrade,
+    /// Set the email sender config without upgrading the canister
+    SetEmailSenderConfig,
 }
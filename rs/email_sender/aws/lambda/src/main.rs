@@ -4,9 +4,7 @@ use aws_lambda_events::sqs::SqsMessage;
 use aws_sdk_sesv2::types::builders::{DestinationBuilder, EmailContentBuilder, TemplateBuilder};
 use aws_sdk_sesv2::Client as SesClient;
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
-use magic_links::SignedMagicLink;
-use rsa::pkcs1::DecodeRsaPrivateKey;
-use rsa::RsaPrivateKey;
+use magic_links::{SignedMagicLink, SigningKey};
 use serde::Serialize;
 use tracing::{error, info};
 
@@ -26,11 +24,14 @@ async fn main() -> Result<(), Error> {
 async fn function_handler(event: LambdaEvent<SqsEvent>) -> Result<(), Error> {
     let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
     let ses_client = SesClient::new(&aws_config);
-    let rsa_private_key_pem = std::env::var("RSA_PRIVATE_KEY_PEM")?.replace("\\n", "\n");
-    let rsa_private_key = RsaPrivateKey::from_pkcs1_pem(&rsa_private_key_pem)?;
+    // Either an RSA or an Ed25519 key, matching the public key configured in the canister
+    let private_key_pem = std::env::var("SIGNING_KEY_PEM")
+        .or_else(|_| std::env::var("RSA_PRIVATE_KEY_PEM"))?
+        .replace("\\n", "\n");
+    let signing_key = SigningKey::from_pem(&private_key_pem)?;
 
     for event in event.payload.records {
-        if let Err(error) = process_record(event, rsa_private_key.clone(), &ses_client).await {
+        if let Err(error) = process_record(event, &signing_key, &ses_client).await {
             error!(?error, "Error processing record");
         }
     }
@@ -40,7 +41,7 @@ async fn function_handler(event: LambdaEvent<SqsEvent>) -> Result<(), Error> {
 
 async fn process_record(
     message: SqsMessage,
-    rsa_private_key: RsaPrivateKey,
+    signing_key: &SigningKey,
     ses_client: &SesClient,
 ) -> Result<(), Error> {
     let body = message.body.unwrap_or_default();
@@ -50,7 +51,7 @@ async fn process_record(
     let magic_link: SignedMagicLink = serde_json::from_str(&body)?;
     let email = magic_link.magic_link.email().to_string();
 
-    let signed = magic_link.sign(rsa_private_key);
+    let signed = magic_link.sign(signing_key);
 
     let querystring = signed.build_querystring();
     let magic_link_url = format!("https://oc.app/home{querystring}");
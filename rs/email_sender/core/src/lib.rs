@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use magic_links::SignedMagicLink;
+use std::sync::Mutex;
 
 #[async_trait]
 pub trait EmailSender: Send + Sync {
@@ -15,3 +16,126 @@ impl EmailSender for NullEmailSender {
         Ok(())
     }
 }
+
+// Tries each sender in order until one succeeds. Senders which fail `max_consecutive_failures` times
+// in a row are moved to the back of the queue until `cooldown` has passed, after which their failure
+// count starts again from zero.
+pub struct FailoverEmailSender {
+    senders: Vec<Box<dyn EmailSender>>,
+    max_consecutive_failures: u32,
+    cooldown_millis: u64,
+    // Sends can take a while to complete, so the current time is read again after each one
+    now_millis: fn() -> u64,
+    health: Mutex<Vec<SenderHealth>>,
+}
+
+#[derive(Clone, Default)]
+struct SenderHealth {
+    consecutive_failures: u32,
+    unhealthy_until: Option<u64>,
+}
+
+impl FailoverEmailSender {
+    pub fn new(
+        senders: Vec<Box<dyn EmailSender>>,
+        max_consecutive_failures: u32,
+        cooldown_millis: u64,
+        now_millis: fn() -> u64,
+    ) -> FailoverEmailSender {
+        let health = vec![SenderHealth::default(); senders.len()];
+
+        FailoverEmailSender {
+            senders,
+            max_consecutive_failures: max_consecutive_failures.max(1),
+            cooldown_millis,
+            now_millis,
+            health: Mutex::new(health),
+        }
+    }
+
+    // Healthy senders are tried first, unhealthy ones are only tried if all healthy ones fail
+    fn send_order(&self, now_millis: u64) -> Vec<usize> {
+        let mut health = self.health.lock().unwrap();
+        for sender_health in health.iter_mut() {
+            if sender_health
+                .unhealthy_until
+                .is_some_and(|until| until <= now_millis)
+            {
+                *sender_health = SenderHealth::default();
+            }
+        }
+
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) =
+            (0..self.senders.len()).partition(|&i| health[i].unhealthy_until.is_none());
+
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    fn record_result(&self, index: usize, success: bool, now_millis: u64) {
+        let mut health = self.health.lock().unwrap();
+        let sender_health = &mut health[index];
+
+        if success {
+            *sender_health = SenderHealth::default();
+        } else {
+            sender_health.consecutive_failures += 1;
+            if sender_health.consecutive_failures >= self.max_consecutive_failures {
+                sender_health.unhealthy_until = Some(now_millis + self.cooldown_millis);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EmailSender for FailoverEmailSender {
+    async fn send(&self, magic_link: SignedMagicLink, now_millis: u64) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        for index in self.send_order(now_millis) {
+            match self.senders[index]
+                .send(magic_link.clone(), (self.now_millis)())
+                .await
+            {
+                Ok(()) => {
+                    self.record_result(index, true, (self.now_millis)());
+                    return Ok(());
+                }
+                Err(error) => {
+                    self.record_result(index, false, (self.now_millis)());
+                    errors.push(format!("Sender {index}: {error}"));
+                }
+            }
+        }
+
+        Err(errors.join(". "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failover_sender_recovers_after_cooldown() {
+        let senders: Vec<Box<dyn EmailSender>> = vec![
+            Box::new(NullEmailSender::default()),
+            Box::new(NullEmailSender::default()),
+        ];
+        let failover = FailoverEmailSender::new(senders, 2, 1000, || 0);
+
+        failover.record_result(0, false, 0);
+        assert_eq!(failover.send_order(0), vec![0, 1]);
+        failover.record_result(0, false, 0);
+        assert_eq!(failover.send_order(0), vec![1, 0]);
+        assert_eq!(failover.send_order(999), vec![1, 0]);
+
+        // Once the cooldown has passed a single failure isn't enough to skip the sender again
+        assert_eq!(failover.send_order(1000), vec![0, 1]);
+        failover.record_result(0, false, 1000);
+        assert_eq!(failover.send_order(1000), vec![0, 1]);
+
+        failover.record_result(0, true, 1000);
+        failover.record_result(0, false, 1000);
+        assert_eq!(failover.send_order(1000), vec![0, 1]);
+    }
+}
@@ -0,0 +1,130 @@
+use async_trait::async_trait;
+use email_sender_core::EmailSender;
+use hmac::{Hmac, Mac};
+use ic_cdk::api::management_canister::http_request::{
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext, TransformFunc,
+};
+use ic_cdk::query;
+use magic_links::SignedMagicLink;
+use sha2::Sha256;
+
+pub const SIGNATURE_HEADER: &str = "x-signature";
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+
+// Posts the signed magic link as JSON to a relay which is then responsible for signing the link
+// using the email sender's key and sending the email, eg. via SMTP. Each request is signed using
+// HMAC-SHA256 so that the relay can verify that it came from the canister.
+//
+// Each replica in the subnet makes the request, so the relay must handle receiving duplicates.
+pub struct HttpRelayEmailSender {
+    url: String,
+    hmac_key: String,
+}
+
+impl HttpRelayEmailSender {
+    pub fn new(url: String, hmac_key: String) -> HttpRelayEmailSender {
+        HttpRelayEmailSender { url, hmac_key }
+    }
+
+    fn build_args(
+        &self,
+        magic_link: SignedMagicLink,
+        now_millis: u64,
+    ) -> CanisterHttpRequestArgument {
+        let body = serde_json::to_vec(&magic_link).unwrap();
+        let signature = sign(self.hmac_key.as_bytes(), now_millis, &body);
+
+        let headers = vec![
+            HttpHeader {
+                name: "content-type".to_string(),
+                value: "application/json".to_string(),
+            },
+            HttpHeader {
+                name: TIMESTAMP_HEADER.to_string(),
+                value: now_millis.to_string(),
+            },
+            HttpHeader {
+                name: SIGNATURE_HEADER.to_string(),
+                value: signature,
+            },
+        ];
+
+        CanisterHttpRequestArgument {
+            url: self.url.clone(),
+            max_response_bytes: Some(5 * 1024), // 5KB
+            method: HttpMethod::POST,
+            headers,
+            body: Some(body),
+            transform: Some(TransformContext {
+                function: TransformFunc::new(
+                    ic_cdk::id(),
+                    "http_relay_email_sender_transform_http_response".to_string(),
+                ),
+                context: Vec::new(),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl EmailSender for HttpRelayEmailSender {
+    async fn send(&self, magic_link: SignedMagicLink, now_millis: u64) -> Result<(), String> {
+        let args = self.build_args(magic_link, now_millis);
+
+        let (resp,) =
+            ic_cdk::api::management_canister::http_request::http_request(args, 1_000_000_000)
+                .await
+                .map_err(|e| format!("{e:?}"))?;
+
+        if u32::try_from(resp.status.0.clone()).ok() == Some(200) {
+            Ok(())
+        } else {
+            Err(format!("Response code: {}", resp.status))
+        }
+    }
+}
+
+// The timestamp is included in the signature so that the relay can reject stale requests
+pub fn sign(hmac_key: &[u8], timestamp: u64, body: &[u8]) -> String {
+    hex::encode(hmac(hmac_key, timestamp, body).finalize().into_bytes())
+}
+
+pub fn verify(hmac_key: &[u8], timestamp: u64, body: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    hmac(hmac_key, timestamp, body)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn hmac(hmac_key: &[u8], timestamp: u64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_key).unwrap();
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    mac
+}
+
+#[query(name = "http_relay_email_sender_transform_http_response")]
+fn transform_http_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let signature = sign(b"key", 1000, b"body");
+
+        assert!(verify(b"key", 1000, b"body", &signature));
+        assert!(!verify(b"key", 1001, b"body", &signature));
+        assert!(!verify(b"key", 1000, b"bodies", &signature));
+        assert!(!verify(b"other_key", 1000, b"body", &signature));
+    }
+}
@@ -3,11 +3,16 @@ use crate::setup::setup_new_env;
 use crate::{canister_wasm, TestEnv};
 use candid::{CandidType, Principal};
 use ic_http_certification::{HttpRequest, HttpResponse};
-use pocket_ic::{PocketIc, UserError, WasmResult};
+use pocket_ic::common::rest::RawMessageId;
+use pocket_ic::{CallError, PocketIc, UserError, WasmResult};
 use serde::de::DeserializeOwned;
 use sign_in_with_email_canister::{
-    GenerateMagicLinkArgs, GenerateMagicLinkResponse, GetDelegationArgs, GetDelegationResponse,
-    InitOrUpgradeArgs, UpgradeArgs,
+    EmailSenderConfigResponse, EncryptedEmailSenderConfig, GenerateMagicLinkArgs,
+    GenerateMagicLinkResponse, GetDelegationArgs, GetDelegationResponse, GetEmailStatsArgs,
+    GetEmailStatsResponse, HandleMagicLinkArgs, HandleMagicLinkResponse, InitArgs,
+    InitOrUpgradeArgs, MagicLinkStatusArgs, MagicLinkStatusResponse, Metrics, RotateRsaKeyResponse,
+    RsaPublicKeysResponse, SetEmailSenderConfigResponse, SetEmailSenderPublicKeysArgs,
+    SetEmailSenderPublicKeysResponse, UpgradeArgs,
 };
 use test_utils::default_init_args;
 
@@ -20,6 +25,38 @@ pub fn generate_magic_link(
     execute_update(env, sender, canister_id, "generate_magic_link", args)
 }
 
+// Submits the call without awaiting it, so that any HTTP outcalls it makes can be mocked
+pub fn submit_generate_magic_link(
+    env: &PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    args: &GenerateMagicLinkArgs,
+) -> RawMessageId {
+    env.submit_call(
+        canister_id,
+        sender,
+        "generate_magic_link",
+        candid::encode_one(args).unwrap(),
+    )
+    .unwrap()
+}
+
+pub fn await_generate_magic_link(
+    env: &PocketIc,
+    message_id: RawMessageId,
+) -> GenerateMagicLinkResponse {
+    unwrap_response(env.await_call(message_id))
+}
+
+pub fn handle_magic_link(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    args: &HandleMagicLinkArgs,
+) -> HandleMagicLinkResponse {
+    execute_update(env, sender, canister_id, "handle_magic_link", args)
+}
+
 pub fn http_request(
     env: &PocketIc,
     sender: Principal,
@@ -47,18 +84,95 @@ pub fn get_delegation(
     execute_query(env, sender, canister_id, "get_delegation", args)
 }
 
+pub fn magic_link_status(
+    env: &PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    args: &MagicLinkStatusArgs,
+) -> MagicLinkStatusResponse {
+    execute_query(env, sender, canister_id, "magic_link_status", args)
+}
+
+pub fn get_email_stats(
+    env: &PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    args: &GetEmailStatsArgs,
+) -> GetEmailStatsResponse {
+    execute_query(env, sender, canister_id, "get_email_stats", args)
+}
+
+pub fn rotate_rsa_key(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+) -> RotateRsaKeyResponse {
+    execute_update(env, sender, canister_id, "rotate_rsa_key", &())
+}
+
+pub fn rsa_public_key(env: &PocketIc, sender: Principal, canister_id: Principal) -> Option<String> {
+    execute_query(env, sender, canister_id, "rsa_public_key", &())
+}
+
+pub fn rsa_public_keys(
+    env: &PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+) -> Option<RsaPublicKeysResponse> {
+    execute_query(env, sender, canister_id, "rsa_public_keys", &())
+}
+
+pub fn set_email_sender_config(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    args: &EncryptedEmailSenderConfig,
+) -> SetEmailSenderConfigResponse {
+    execute_update(env, sender, canister_id, "set_email_sender_config", args)
+}
+
+pub fn email_sender_config(
+    env: &PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+) -> EmailSenderConfigResponse {
+    execute_query(env, sender, canister_id, "email_sender_config", &())
+}
+
+pub fn set_email_sender_public_keys(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    args: &SetEmailSenderPublicKeysArgs,
+) -> SetEmailSenderPublicKeysResponse {
+    execute_update(
+        env,
+        sender,
+        canister_id,
+        "set_email_sender_public_keys",
+        args,
+    )
+}
+
+pub fn metrics(env: &PocketIc, sender: Principal, canister_id: Principal) -> Metrics {
+    execute_query(env, sender, canister_id, "metrics", &())
+}
+
 pub fn install_canister() -> TestEnv {
+    install_canister_with_args(default_init_args().to_init_args())
+}
+
+pub fn install_canister_with_args(init_args: InitArgs) -> TestEnv {
     let env = setup_new_env();
     let controller = random_principal();
     let wasm = canister_wasm();
-    let init_args = default_init_args();
 
     let canister_id = env.create_canister_with_settings(Some(controller), None);
     env.add_cycles(canister_id, 1_000_000_000_000);
     env.install_canister(
         canister_id,
         wasm,
-        candid::encode_one(init_args).unwrap(),
+        candid::encode_one(InitOrUpgradeArgs::Init(init_args)).unwrap(),
         Some(controller),
     );
     env.tick();
@@ -76,6 +190,15 @@ pub fn upgrade_canister(
     sender: Principal,
     args: Option<UpgradeArgs>,
 ) {
+    try_upgrade_canister(env, canister_id, sender, args).unwrap();
+}
+
+pub fn try_upgrade_canister(
+    env: &mut PocketIc,
+    canister_id: Principal,
+    sender: Principal,
+    args: Option<UpgradeArgs>,
+) -> Result<(), CallError> {
     let wasm = canister_wasm();
     let args = InitOrUpgradeArgs::Upgrade(args.unwrap_or_default());
 
@@ -90,7 +213,6 @@ pub fn upgrade_canister(
         candid::encode_one(args).unwrap(),
         Some(sender),
     )
-    .unwrap();
 }
 
 fn execute_query<P: CandidType, R: CandidType + DeserializeOwned>(
@@ -1,12 +1,33 @@
 use crate::identity::create_session_identity;
 use crate::rng::random_principal;
 use crate::{client, TestEnv};
+use candid::Principal;
 use ic_agent::Identity;
-use ic_http_certification::HttpRequest;
+use ic_http_certification::{
+    HttpRequest, HttpResponse, CERTIFICATE_EXPRESSION_HEADER_NAME, CERTIFICATE_HEADER_NAME,
+};
+use magic_links::{DoubleSignedMagicLink, SignedMagicLink, SigningKey};
+use pocket_ic::common::rest::{CanisterHttpReply, CanisterHttpResponse, MockCanisterHttpResponse};
+use pocket_ic::PocketIc;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::RsaPublicKey;
 use sign_in_with_email_canister::{
-    GenerateMagicLinkArgs, GenerateMagicLinkResponse, GetDelegationArgs, GetDelegationResponse,
+    AwsEmailSenderConfig, Branding, EmailRateLimitPolicy, EmailSenderConfig,
+    EmailSenderConfigPublic, EmailSenderPublicKey, EncryptedEmailSenderConfig,
+    FailoverEmailSenderConfig, GenerateMagicLinkArgs, GenerateMagicLinkResponse,
+    GenerateMagicLinkSuccess, GetDelegationArgs, GetDelegationResponse, GetEmailStatsArgs,
+    GetEmailStatsResponse, HandleMagicLinkArgs, HandleMagicLinkResponse,
+    HttpRelayEmailSenderConfig, InitArgs, MagicLinkExpiryPolicy, MagicLinkSignatureScheme,
+    MagicLinkStatusArgs, MagicLinkStatusResponse, RotateRsaKeyResponse,
+    SetEmailSenderConfigResponse, SetEmailSenderPublicKeysArgs, SetEmailSenderPublicKeysResponse,
+    SlidingWindowLimit, ThrottlePolicy, UpgradeArgs, VerificationCodeAlphabet,
+    VerificationCodeFormat, NANOS_PER_MILLISECOND, ONE_DAY, ONE_MINUTE,
+};
+use std::time::{Duration, UNIX_EPOCH};
+use test_utils::{
+    default_init_args, ed25519_signing_key, email_sender_rsa_signing_key, generate_magic_link,
+    generate_magic_link_with_keys, rsa_signing_key,
 };
-use test_utils::generate_magic_link;
 
 #[test]
 fn end_to_end() {
@@ -29,6 +50,7 @@ fn end_to_end() {
             email: email.to_string(),
             session_key: session_key.clone(),
             max_time_to_live: None,
+            magic_link_time_to_live: None,
         },
     );
 
@@ -44,6 +66,7 @@ fn end_to_end() {
         generate_magic_link_success.created,
         generate_magic_link_success.expiration,
         generate_magic_link_success.code.clone(),
+        generate_magic_link_success.magic_link_expiration - generate_magic_link_success.created,
     );
 
     let http_request = HttpRequest {
@@ -95,3 +118,1752 @@ fn upgrade_canister_succeeds() {
 
     client::upgrade_canister(&mut env, canister_id, controller, None);
 }
+
+#[test]
+fn generate_magic_link_blocked_after_free_emails() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+    let policy = EmailRateLimitPolicy::default();
+
+    for _ in 0..policy.free_emails {
+        let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+        assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+    }
+
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+    let GenerateMagicLinkResponse::Blocked(retry_after) = response else {
+        panic!("{response:?}");
+    };
+    assert!(retry_after > 0 && retry_after <= policy.initial_backoff);
+
+    // Other email addresses are unaffected
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, "abc@xyz.com");
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+
+    env.advance_time(Duration::from_millis(retry_after));
+
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+
+    // The backoff doubles with each additional email
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+    let GenerateMagicLinkResponse::Blocked(retry_after) = response else {
+        panic!("{response:?}");
+    };
+    assert!(retry_after > policy.initial_backoff && retry_after <= 2 * policy.initial_backoff);
+
+    // Once `reset_after` has passed the backoff is reset
+    env.advance_time(Duration::from_millis(policy.reset_after));
+
+    for _ in 0..policy.free_emails {
+        let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+        assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+    }
+}
+
+#[test]
+fn email_rate_limit_policy_can_be_set_on_upgrade() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let policy = EmailRateLimitPolicy {
+        free_emails: 1,
+        initial_backoff: 5 * ONE_MINUTE,
+        max_backoff: 10 * ONE_MINUTE,
+        reset_after: 60 * ONE_MINUTE,
+    };
+
+    client::upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            email_rate_limit_policy: Some(policy.clone()),
+            ..Default::default()
+        }),
+    );
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+    let GenerateMagicLinkResponse::Blocked(retry_after) = response else {
+        panic!("{response:?}");
+    };
+    assert!(retry_after > ONE_MINUTE && retry_after <= policy.initial_backoff);
+}
+
+#[test]
+fn generate_magic_link_throttled_per_caller() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let policy = ThrottlePolicy::default();
+
+    for i in 0..policy.per_caller.max_requests {
+        let email = format!("{i}@blah.com");
+        let response = generate_magic_link_for_email(&mut env, sender, canister_id, &email);
+        assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+    }
+
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, "abc@blah.com");
+    let GenerateMagicLinkResponse::Throttled(retry_after) = response else {
+        panic!("{response:?}");
+    };
+    assert!(retry_after > 0 && retry_after <= policy.per_caller.window);
+
+    // Other callers are unaffected
+    let response =
+        generate_magic_link_for_email(&mut env, random_principal(), canister_id, "abc@blah.com");
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+
+    env.advance_time(Duration::from_millis(retry_after));
+
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, "xyz@blah.com");
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+}
+
+#[test]
+fn anonymous_callers_share_a_stricter_bucket() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    client::upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            throttle_policy: Some(ThrottlePolicy {
+                anonymous: SlidingWindowLimit {
+                    max_requests: 2,
+                    window: ONE_MINUTE,
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    );
+
+    let anonymous = Principal::anonymous();
+
+    for i in 0..2 {
+        let email = format!("{i}@blah.com");
+        let response = generate_magic_link_for_email(&mut env, anonymous, canister_id, &email);
+        assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+    }
+
+    let response = generate_magic_link_for_email(&mut env, anonymous, canister_id, "abc@blah.com");
+    assert!(matches!(response, GenerateMagicLinkResponse::Throttled(_)));
+
+    let response =
+        generate_magic_link_for_email(&mut env, random_principal(), canister_id, "abc@blah.com");
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+}
+
+#[test]
+fn generate_magic_link_throttled_globally() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    client::upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            throttle_policy: Some(ThrottlePolicy {
+                global_emails_per_minute: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    );
+
+    for i in 0..3 {
+        let email = format!("{i}@blah.com");
+        let response =
+            generate_magic_link_for_email(&mut env, random_principal(), canister_id, &email);
+        assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+    }
+
+    let response =
+        generate_magic_link_for_email(&mut env, random_principal(), canister_id, "abc@blah.com");
+    let GenerateMagicLinkResponse::Throttled(retry_after) = response else {
+        panic!("{response:?}");
+    };
+
+    env.advance_time(Duration::from_millis(retry_after));
+
+    let response =
+        generate_magic_link_for_email(&mut env, random_principal(), canister_id, "abc@blah.com");
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+}
+
+#[test]
+fn magic_link_locked_after_too_many_incorrect_codes() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+    let incorrect_code = incorrect_code(&success.code);
+
+    // Incorrect codes can't be recorded in query calls so they must be upgraded
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &auth_http_request(&signed, &incorrect_code),
+    );
+    assert!(http_response.upgrade.unwrap());
+
+    for _ in 0..2 {
+        let response = client::handle_magic_link(
+            &mut env,
+            sender,
+            canister_id,
+            &HandleMagicLinkArgs {
+                link: auth_link(&signed, &incorrect_code),
+            },
+        );
+        assert!(matches!(response, HandleMagicLinkResponse::CodeIncorrect));
+    }
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &incorrect_code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::TooManyAttempts));
+
+    // The link remains locked even when the correct code is then supplied
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::TooManyAttempts));
+
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &auth_http_request(&signed, &success.code),
+    );
+    assert_eq!(http_response.status_code, 400);
+    assert!(http_response.upgrade.is_none());
+}
+
+#[test]
+fn max_incorrect_code_attempts_can_be_configured() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    client::upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            max_incorrect_code_attempts: Some(1),
+            ..Default::default()
+        }),
+    );
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &incorrect_code(&success.code)),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::TooManyAttempts));
+}
+
+#[test]
+fn magic_link_can_only_be_used_once() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+    let http_request = auth_http_request(&signed, &success.code);
+
+    let http_response = client::http_request(&env, sender, canister_id, &http_request);
+    assert!(http_response.upgrade.unwrap());
+
+    let http_response = client::http_request_update(&mut env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 200);
+
+    // Replaying the link via the query path no longer requests an upgrade
+    let http_response = client::http_request(&env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 400);
+    assert!(http_response.upgrade.is_none());
+
+    let http_response = client::http_request_update(&mut env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 400);
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::LinkAlreadyUsed));
+}
+
+#[test]
+fn magic_link_rejected_if_never_sent() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let identity = create_session_identity();
+    let now = env
+        .get_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    let signed = generate_magic_link(
+        "blah@blah.com",
+        identity.public_key().unwrap(),
+        now,
+        (now + ONE_DAY) * NANOS_PER_MILLISECOND,
+        "123".to_string(),
+        10 * ONE_MINUTE,
+    );
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, "123"),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::LinkInvalid(_)));
+}
+
+#[test]
+fn verification_code_format_can_be_configured() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    client::upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            verification_code_format: Some(VerificationCodeFormat {
+                length: 8,
+                alphabet: VerificationCodeAlphabet::CrockfordBase32,
+            }),
+            ..Default::default()
+        }),
+    );
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    assert_eq!(success.code.len(), 8);
+    assert!(success
+        .code
+        .chars()
+        .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase()));
+
+    // Codes are case-insensitive
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code.to_lowercase()),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::Success));
+}
+
+#[test]
+fn links_remain_valid_after_verification_code_format_changed() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    client::upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            verification_code_format: Some(VerificationCodeFormat {
+                length: 8,
+                alphabet: VerificationCodeAlphabet::CrockfordBase32,
+            }),
+            ..Default::default()
+        }),
+    );
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::Success));
+}
+
+#[test]
+fn magic_link_time_to_live_can_be_requested() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+    let identity = create_session_identity();
+    let session_key = identity.public_key().unwrap();
+    let policy = MagicLinkExpiryPolicy::default();
+
+    // Requested values are clamped to the bounds set by the policy
+    let response = client::generate_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: email.to_string(),
+            session_key: session_key.clone(),
+            max_time_to_live: None,
+            magic_link_time_to_live: Some(ONE_DAY),
+        },
+    );
+    let GenerateMagicLinkResponse::Success(success) = response else {
+        panic!("{response:?}");
+    };
+    assert_eq!(
+        success.magic_link_expiration - success.created,
+        policy.max_time_to_live
+    );
+
+    let response = client::generate_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: email.to_string(),
+            session_key: session_key.clone(),
+            max_time_to_live: None,
+            magic_link_time_to_live: Some(2 * ONE_MINUTE),
+        },
+    );
+    let GenerateMagicLinkResponse::Success(success) = response else {
+        panic!("{response:?}");
+    };
+    assert_eq!(
+        success.magic_link_expiration - success.created,
+        2 * ONE_MINUTE
+    );
+
+    let signed = generate_magic_link(
+        email,
+        session_key,
+        success.created,
+        success.expiration,
+        success.code.clone(),
+        2 * ONE_MINUTE,
+    );
+
+    env.advance_time(Duration::from_millis(3 * ONE_MINUTE));
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::LinkExpired));
+}
+
+#[test]
+fn magic_link_status_tracks_sign_in_progress() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+    let (success, signed) = generate_and_sign_magic_link(&mut env, sender, canister_id, email);
+    let status_args = MagicLinkStatusArgs {
+        email: email.to_string(),
+        session_key: signed.magic_link.delegation().pubkey.clone(),
+        expiration: success.expiration,
+    };
+
+    let status = client::magic_link_status(&env, sender, canister_id, &status_args);
+    assert!(matches!(status, MagicLinkStatusResponse::Pending));
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::Success));
+
+    let status = client::magic_link_status(&env, sender, canister_id, &status_args);
+    assert!(matches!(status, MagicLinkStatusResponse::Verified));
+
+    let (success, signed) = generate_and_sign_magic_link(&mut env, sender, canister_id, email);
+    let status_args = MagicLinkStatusArgs {
+        email: email.to_string(),
+        session_key: signed.magic_link.delegation().pubkey.clone(),
+        expiration: success.expiration,
+    };
+
+    env.advance_time(Duration::from_millis(
+        success.magic_link_expiration - success.created + 1,
+    ));
+
+    let status = client::magic_link_status(&env, sender, canister_id, &status_args);
+    assert!(matches!(status, MagicLinkStatusResponse::Expired));
+
+    let status = client::magic_link_status(
+        &env,
+        sender,
+        canister_id,
+        &MagicLinkStatusArgs {
+            email: "abc@xyz.com".to_string(),
+            ..status_args
+        },
+    );
+    assert!(matches!(status, MagicLinkStatusResponse::Unknown));
+}
+
+#[test]
+fn email_stats_and_metrics_available_to_whitelisted_principals() {
+    let whitelisted_principal = random_principal();
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister_with_args(InitArgs {
+        whitelisted_principals: vec![whitelisted_principal],
+        ..default_init_args().to_init_args()
+    });
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+    let (success, signed) = generate_and_sign_magic_link(&mut env, sender, canister_id, email);
+    generate_and_sign_magic_link(&mut env, sender, canister_id, "other@blah.com");
+
+    let http_request = auth_http_request(&signed, &success.code);
+    let http_response = client::http_request_update(&mut env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 200);
+
+    let GetEmailStatsResponse::Success(stats_by_email) = client::get_email_stats(
+        &env,
+        whitelisted_principal,
+        canister_id,
+        &GetEmailStatsArgs::Email(email.to_string()),
+    ) else {
+        panic!();
+    };
+    assert_eq!(stats_by_email.emails_sent, 1);
+    assert_eq!(stats_by_email.successful_links, 1);
+    assert!(stats_by_email.latest_successful_link.is_some());
+
+    let GetEmailStatsResponse::Success(stats_by_principal) = client::get_email_stats(
+        &env,
+        whitelisted_principal,
+        canister_id,
+        &GetEmailStatsArgs::Principal(Principal::self_authenticating(&success.user_key)),
+    ) else {
+        panic!();
+    };
+    assert_eq!(stats_by_principal.first_seen, stats_by_email.first_seen);
+
+    let response = client::get_email_stats(
+        &env,
+        whitelisted_principal,
+        canister_id,
+        &GetEmailStatsArgs::Email("unknown@blah.com".to_string()),
+    );
+    assert!(matches!(response, GetEmailStatsResponse::NotFound));
+
+    let metrics = client::metrics(&env, whitelisted_principal, canister_id);
+    assert_eq!(metrics.total_emails_sent, 2);
+    assert_eq!(metrics.total_successful_links, 1);
+    assert_eq!(metrics.success_ratio, 0.5);
+    assert_eq!(metrics.active_magic_links, 1);
+    assert_eq!(metrics.distinct_users, 2);
+}
+
+#[test]
+fn metrics_endpoint_renders_prometheus_metrics() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    let http_response = client::http_request_update(
+        &mut env,
+        sender,
+        canister_id,
+        &auth_http_request(&signed, &incorrect_code(&success.code)),
+    );
+    assert_eq!(http_response.status_code, 400);
+
+    let http_response = client::http_request_update(
+        &mut env,
+        sender,
+        canister_id,
+        &auth_http_request(&signed, &success.code),
+    );
+    assert_eq!(http_response.status_code, 200);
+
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &HttpRequest {
+            method: "GET".to_string(),
+            url: "https://canister_id.icp0.io/metrics".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        },
+    );
+    assert_eq!(http_response.status_code, 200);
+
+    let body = String::from_utf8(http_response.body).unwrap();
+    assert!(body.contains("sign_in_with_email_emails_sent_total 1\n"));
+    assert!(body.contains("sign_in_with_email_emails_failed_total 0\n"));
+    assert!(body.contains("sign_in_with_email_signature_map_size 1\n"));
+    assert!(body.contains("sign_in_with_email_active_magic_links 0\n"));
+    assert!(body.contains("sign_in_with_email_auth_results_total{result=\"success\"} 1\n"));
+    assert!(body.contains("sign_in_with_email_auth_results_total{result=\"code_incorrect\"} 1\n"));
+}
+
+#[test]
+fn expired_signatures_are_pruned() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    for round in 0..5 {
+        for i in 0..10 {
+            let sender = random_principal();
+            let email = format!("{round}_{i}@blah.com");
+            let (success, signed) =
+                generate_and_sign_magic_link(&mut env, sender, canister_id, &email);
+
+            let http_request = auth_http_request(&signed, &success.code);
+            let http_response =
+                client::http_request_update(&mut env, sender, canister_id, &http_request);
+            assert_eq!(http_response.status_code, 200);
+        }
+
+        // Only the signatures from the latest round remain, the rest were pruned once expired
+        assert_eq!(signature_map_size(&env, canister_id), 10);
+
+        env.advance_time(Duration::from_millis(11 * ONE_MINUTE));
+    }
+}
+
+#[test]
+fn signatures_persisted_across_upgrades() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+    let (success, signed) = generate_and_sign_magic_link(&mut env, sender, canister_id, email);
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::Success));
+
+    client::upgrade_canister(&mut env, canister_id, controller, None);
+
+    let get_delegation_response = client::get_delegation(
+        &env,
+        sender,
+        canister_id,
+        &GetDelegationArgs {
+            email: email.to_string(),
+            session_key: signed.magic_link.delegation().pubkey.clone(),
+            expiration: success.expiration,
+        },
+    );
+    assert!(matches!(
+        get_delegation_response,
+        GetDelegationResponse::Success(_)
+    ));
+}
+
+#[test]
+fn magic_links_persisted_across_upgrades() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+    let policy = EmailRateLimitPolicy::default();
+
+    let (success, signed) = generate_and_sign_magic_link(&mut env, sender, canister_id, email);
+    for _ in 1..policy.free_emails {
+        let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+        assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+    }
+
+    client::upgrade_canister(&mut env, canister_id, controller, None);
+
+    // The email stats survive the upgrade so the address is still rate limited
+    let response = generate_magic_link_for_email(&mut env, sender, canister_id, email);
+    assert!(matches!(response, GenerateMagicLinkResponse::Blocked(_)));
+
+    // And the magic link sent prior to the upgrade is still active
+    let http_request = auth_http_request(&signed, &success.code);
+    let http_response = client::http_request_update(&mut env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 200);
+}
+
+#[test]
+fn magic_link_signature_scheme_can_be_changed() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (rsa_success, rsa_signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "rsa@blah.com");
+
+    client::upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            magic_link_signature_scheme: Some(MagicLinkSignatureScheme::Ed25519),
+            ..Default::default()
+        }),
+    );
+
+    let identity = create_session_identity();
+    let session_key = identity.public_key().unwrap();
+    let response = client::generate_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: "ed25519@blah.com".to_string(),
+            session_key: session_key.clone(),
+            max_time_to_live: None,
+            magic_link_time_to_live: None,
+        },
+    );
+    let GenerateMagicLinkResponse::Success(ed25519_success) = response else {
+        panic!("{response:?}");
+    };
+    let ed25519_signed = generate_magic_link_with_keys(
+        "ed25519@blah.com",
+        session_key,
+        ed25519_success.created,
+        ed25519_success.expiration,
+        ed25519_success.code.clone(),
+        ed25519_success.magic_link_expiration - ed25519_success.created,
+        &ed25519_signing_key(),
+        &test_utils::email_sender_rsa_signing_key(),
+    );
+    assert_eq!(ed25519_signed.signature1.len(), 64);
+
+    // Links signed before the scheme was changed remain valid
+    for (success, signed) in [(rsa_success, rsa_signed), (ed25519_success, ed25519_signed)] {
+        let response = client::handle_magic_link(
+            &mut env,
+            sender,
+            canister_id,
+            &HandleMagicLinkArgs {
+                link: auth_link(&signed, &success.code),
+            },
+        );
+        assert!(matches!(response, HandleMagicLinkResponse::Success));
+    }
+}
+
+#[test]
+fn rsa_key_can_be_rotated() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    let previous = client::rsa_public_keys(&env, sender, canister_id).unwrap();
+    assert!(previous.previous.is_empty());
+
+    let response = client::rotate_rsa_key(&mut env, controller, canister_id);
+    let RotateRsaKeyResponse::Success(current_pem) = response else {
+        panic!("{response:?}");
+    };
+    assert_ne!(current_pem, previous.current);
+
+    let rsa_public_key = client::rsa_public_key(&env, sender, canister_id).unwrap();
+    assert_eq!(rsa_public_key, current_pem);
+
+    let rsa_public_keys = client::rsa_public_keys(&env, sender, canister_id).unwrap();
+    assert_eq!(rsa_public_keys.current, current_pem);
+    assert_eq!(rsa_public_keys.previous.len(), 1);
+    assert_eq!(rsa_public_keys.previous[0].public_key_pem, previous.current);
+    assert!(rsa_public_keys.previous[0].valid_until >= success.magic_link_expiration);
+
+    // Links signed with the previous key remain valid
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: auth_link(&signed, &success.code),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::Success));
+}
+
+#[test]
+fn email_sender_key_can_be_rotated() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let now = env
+        .get_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let previous_key = email_sender_rsa_signing_key();
+    let new_key = SigningKey::Ed25519([3; 32]);
+
+    let response = client::set_email_sender_public_keys(
+        &mut env,
+        controller,
+        canister_id,
+        &SetEmailSenderPublicKeysArgs {
+            keys: vec![
+                EmailSenderPublicKey {
+                    public_key_pem: previous_key.public_key().to_pem(),
+                    valid_from: None,
+                    valid_until: Some(now + ONE_MINUTE),
+                },
+                EmailSenderPublicKey {
+                    public_key_pem: new_key.public_key().to_pem(),
+                    valid_from: None,
+                    valid_until: None,
+                },
+            ],
+        },
+    );
+    assert!(matches!(
+        response,
+        SetEmailSenderPublicKeysResponse::Success
+    ));
+
+    let sender = random_principal();
+
+    // Both keys are accepted until the previous key's validity window ends
+    for (email, key) in [("a@blah.com", &previous_key), ("b@blah.com", &new_key)] {
+        let (success, signed) =
+            generate_magic_link_signed_by(&mut env, sender, canister_id, email, key);
+        let response = client::handle_magic_link(
+            &mut env,
+            sender,
+            canister_id,
+            &HandleMagicLinkArgs {
+                link: auth_link(&signed, &success.code),
+            },
+        );
+        assert!(matches!(response, HandleMagicLinkResponse::Success));
+    }
+
+    env.advance_time(Duration::from_millis(2 * ONE_MINUTE));
+
+    for (email, key, valid) in [
+        ("c@blah.com", &previous_key, false),
+        ("d@blah.com", &new_key, true),
+    ] {
+        let (success, signed) =
+            generate_magic_link_signed_by(&mut env, sender, canister_id, email, key);
+        let response = client::handle_magic_link(
+            &mut env,
+            sender,
+            canister_id,
+            &HandleMagicLinkArgs {
+                link: auth_link(&signed, &success.code),
+            },
+        );
+        if valid {
+            assert!(matches!(response, HandleMagicLinkResponse::Success));
+        } else {
+            assert!(matches!(response, HandleMagicLinkResponse::LinkInvalid(_)));
+        }
+    }
+}
+
+#[test]
+fn email_sender_config_can_be_set_without_upgrade() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let rsa_public_key = client::rsa_public_key(&env, controller, canister_id).unwrap();
+    let rsa_public_key = RsaPublicKey::from_public_key_pem(&rsa_public_key).unwrap();
+    let config = EmailSenderConfig::Aws(AwsEmailSenderConfig {
+        region: "eu-west-2".to_string(),
+        function_url: "https://blah.lambda-url.eu-west-2.on.aws".to_string(),
+        access_key: "access_key".to_string(),
+        secret_key: "secret_key".to_string(),
+    });
+    let encrypted = config.encrypt(&rsa_public_key, &mut rand::thread_rng());
+
+    let response = client::set_email_sender_config(&mut env, controller, canister_id, &encrypted);
+    assert!(matches!(response, SetEmailSenderConfigResponse::Success));
+
+    let response = client::email_sender_config(&env, controller, canister_id);
+    let Some(EmailSenderConfigPublic::Aws(aws)) = response.email_sender_config else {
+        panic!();
+    };
+    assert_eq!(aws.region, "eu-west-2");
+
+    let EncryptedEmailSenderConfig::Aws(mut invalid) = encrypted else {
+        panic!();
+    };
+    invalid.secret_key_encrypted = "v1:AAAA".to_string();
+    let response = client::set_email_sender_config(
+        &mut env,
+        controller,
+        canister_id,
+        &EncryptedEmailSenderConfig::Aws(invalid),
+    );
+    assert!(matches!(
+        response,
+        SetEmailSenderConfigResponse::DecryptionFailed(_)
+    ));
+}
+
+#[test]
+fn upgrade_fails_if_email_sender_config_cannot_be_decrypted() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let rsa_public_key = client::rsa_public_key(&env, controller, canister_id).unwrap();
+    let rsa_public_key = RsaPublicKey::from_public_key_pem(&rsa_public_key).unwrap();
+    let config = EmailSenderConfig::Aws(AwsEmailSenderConfig {
+        region: "eu-west-2".to_string(),
+        function_url: "https://blah.lambda-url.eu-west-2.on.aws".to_string(),
+        access_key: "access_key".to_string(),
+        secret_key: "secret_key".to_string(),
+    });
+    let EncryptedEmailSenderConfig::Aws(mut invalid) =
+        config.encrypt(&rsa_public_key, &mut rand::thread_rng())
+    else {
+        panic!();
+    };
+    invalid.secret_key_encrypted = "v1:AAAA".to_string();
+
+    let result = client::try_upgrade_canister(
+        &mut env,
+        canister_id,
+        controller,
+        Some(UpgradeArgs {
+            email_sender_config: Some(EncryptedEmailSenderConfig::Aws(invalid)),
+            ..Default::default()
+        }),
+    );
+    assert!(result.is_err());
+
+    // The upgrade is rolled back so the canister keeps running without the invalid config
+    let response = client::email_sender_config(&env, controller, canister_id);
+    assert!(response.email_sender_config.is_none());
+}
+
+#[test]
+fn http_relay_email_sender_sends_signed_requests() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let url = "https://relay.blah.com/send";
+    let hmac_key = "hmac_key";
+    let rsa_public_key = client::rsa_public_key(&env, controller, canister_id).unwrap();
+    let rsa_public_key = RsaPublicKey::from_public_key_pem(&rsa_public_key).unwrap();
+    let config = EmailSenderConfig::HttpRelay(HttpRelayEmailSenderConfig {
+        url: url.to_string(),
+        hmac_key: hmac_key.to_string(),
+    });
+    let encrypted = config.encrypt(&rsa_public_key, &mut rand::thread_rng());
+
+    let response = client::set_email_sender_config(&mut env, controller, canister_id, &encrypted);
+    assert!(matches!(response, SetEmailSenderConfigResponse::Success));
+
+    let identity = create_session_identity();
+    let message_id = client::submit_generate_magic_link(
+        &env,
+        random_principal(),
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: "blah@blah.com".to_string(),
+            session_key: identity.public_key().unwrap(),
+            max_time_to_live: None,
+            magic_link_time_to_live: None,
+        },
+    );
+    env.tick();
+    env.tick();
+
+    // Acts as the relay, checking the request is signed before accepting it
+    let requests = env.get_canister_http();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    assert_eq!(request.url, url);
+
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|h| h.name == name)
+            .map(|h| h.value.clone())
+            .unwrap()
+    };
+    let timestamp: u64 = header(email_sender_http_relay::TIMESTAMP_HEADER)
+        .parse()
+        .unwrap();
+    let signature = header(email_sender_http_relay::SIGNATURE_HEADER);
+    assert!(email_sender_http_relay::verify(
+        hmac_key.as_bytes(),
+        timestamp,
+        &request.body,
+        &signature
+    ));
+
+    env.mock_canister_http_response(MockCanisterHttpResponse {
+        subnet_id: request.subnet_id,
+        request_id: request.request_id,
+        response: CanisterHttpResponse::CanisterHttpReply(CanisterHttpReply {
+            status: 200,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }),
+        additional_responses: Vec::new(),
+    });
+
+    let response = client::await_generate_magic_link(&env, message_id);
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+}
+
+#[test]
+fn failover_email_sender_skips_failing_sender() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister();
+
+    let url1 = "https://relay1.blah.com/send";
+    let url2 = "https://relay2.blah.com/send";
+    let rsa_public_key = client::rsa_public_key(&env, controller, canister_id).unwrap();
+    let rsa_public_key = RsaPublicKey::from_public_key_pem(&rsa_public_key).unwrap();
+    let relay = |url: &str| {
+        EmailSenderConfig::HttpRelay(HttpRelayEmailSenderConfig {
+            url: url.to_string(),
+            hmac_key: "hmac_key".to_string(),
+        })
+    };
+    let config = EmailSenderConfig::Failover(FailoverEmailSenderConfig {
+        senders: vec![relay(url1), relay(url2)],
+        max_consecutive_failures: Some(1),
+        cooldown: None,
+    });
+    let encrypted = config.encrypt(&rsa_public_key, &mut rand::thread_rng());
+
+    let response = client::set_email_sender_config(&mut env, controller, canister_id, &encrypted);
+    assert!(matches!(response, SetEmailSenderConfigResponse::Success));
+
+    let generate_magic_link = |email: &str, responses: &[(&str, u16)]| {
+        let identity = create_session_identity();
+        let message_id = client::submit_generate_magic_link(
+            &env,
+            random_principal(),
+            canister_id,
+            &GenerateMagicLinkArgs {
+                email: email.to_string(),
+                session_key: identity.public_key().unwrap(),
+                max_time_to_live: None,
+                magic_link_time_to_live: None,
+            },
+        );
+
+        for (url, status) in responses {
+            env.tick();
+            env.tick();
+
+            let requests = env.get_canister_http();
+            assert_eq!(requests.len(), 1);
+            let request = &requests[0];
+            assert_eq!(request.url, *url);
+
+            env.mock_canister_http_response(MockCanisterHttpResponse {
+                subnet_id: request.subnet_id,
+                request_id: request.request_id,
+                response: CanisterHttpResponse::CanisterHttpReply(CanisterHttpReply {
+                    status: *status,
+                    headers: Vec::new(),
+                    body: Vec::new(),
+                }),
+                additional_responses: Vec::new(),
+            });
+        }
+
+        client::await_generate_magic_link(&env, message_id)
+    };
+
+    // The first sender fails so the email is sent using the second
+    let response = generate_magic_link("blah1@blah.com", &[(url1, 500), (url2, 200)]);
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+
+    // The first sender is now unhealthy so the second is tried first
+    let response = generate_magic_link("blah2@blah.com", &[(url2, 200)]);
+    assert!(matches!(response, GenerateMagicLinkResponse::Success(_)));
+
+    // If every sender fails then the errors from each of them are returned
+    let response = generate_magic_link("blah3@blah.com", &[(url2, 500), (url1, 500)]);
+    let GenerateMagicLinkResponse::FailedToSendEmail(error) = response else {
+        panic!("{response:?}");
+    };
+    assert!(error.contains("Sender 0") && error.contains("Sender 1"));
+}
+
+#[test]
+fn query_responses_are_certified() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    env.advance_time(Duration::from_millis(
+        success.magic_link_expiration - success.created + 1,
+    ));
+
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &auth_http_request(&signed, &success.code),
+    );
+    assert_eq!(http_response.status_code, 400);
+    assert!(has_header(&http_response, CERTIFICATE_HEADER_NAME));
+    assert!(has_header(
+        &http_response,
+        CERTIFICATE_EXPRESSION_HEADER_NAME
+    ));
+
+    // Invalid links are served a generic certified page, the reason is only given by update calls
+    let now = env
+        .get_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let never_sent = generate_magic_link(
+        "blah@blah.com",
+        create_session_identity().public_key().unwrap(),
+        now,
+        (now + ONE_DAY) * NANOS_PER_MILLISECOND,
+        "123".to_string(),
+        10 * ONE_MINUTE,
+    );
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &auth_http_request(&never_sent, "123"),
+    );
+    assert_eq!(http_response.status_code, 400);
+    assert!(http_response.upgrade.is_none());
+    assert!(has_header(&http_response, CERTIFICATE_HEADER_NAME));
+
+    // Results which have no certified response are upgraded to an update call instead
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &auth_http_request(&signed, &incorrect_code(&success.code)),
+    );
+    assert!(http_response.upgrade.unwrap());
+    assert!(has_header(&http_response, CERTIFICATE_HEADER_NAME));
+}
+
+#[test]
+fn malformed_magic_links_are_rejected() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    for query in [
+        "",
+        "m=zz&s1=00&s2=00&c=123",
+        "m=0000&s1=00&s2=00&c=123",
+        "l=!!&c=123",
+        "l=AgAA&c=123",
+        "t=unknown&s=AA&c=123",
+    ] {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            url: format!("/auth?{query}"),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        let http_response = client::http_request(&env, sender, canister_id, &request);
+        assert_eq!(http_response.status_code, 400);
+
+        let http_response = client::http_request_update(&mut env, sender, canister_id, &request);
+        assert_eq!(http_response.status_code, 400);
+
+        let response = client::handle_magic_link(
+            &mut env,
+            sender,
+            canister_id,
+            &HandleMagicLinkArgs {
+                link: format!("https://example.com/auth?{query}"),
+            },
+        );
+        assert!(matches!(response, HandleMagicLinkResponse::LinkInvalid(_)));
+    }
+}
+
+#[test]
+fn legacy_hex_links_are_accepted() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    let link = format!(
+        "https://canister_id.icp0.io/auth?auth&m={}&s1={}&s2={}&c={}",
+        hex::encode(signed.magic_link.serialize()),
+        hex::encode(&signed.signature1),
+        hex::encode(&signed.signature2),
+        success.code
+    );
+    assert!(link.len() > auth_link(&signed, &success.code).len());
+
+    let response =
+        client::handle_magic_link(&mut env, sender, canister_id, &HandleMagicLinkArgs { link });
+    assert!(matches!(response, HandleMagicLinkResponse::Success));
+}
+
+#[test]
+fn short_magic_links_resolved_until_link_expires() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        controller,
+    } = client::install_canister_with_args(InitArgs {
+        short_magic_links: Some(true),
+        ..default_init_args().to_init_args()
+    });
+
+    set_http_relay_email_sender_config(&mut env, controller, canister_id);
+
+    let sender = random_principal();
+    let (success, signed) =
+        send_magic_link_via_http_relay(&mut env, sender, canister_id, "a@blah.com");
+
+    // Acts as the relay, adding the email sender's signature to the link
+    let querystring = signed
+        .sign(&email_sender_rsa_signing_key())
+        .build_querystring();
+    assert!(querystring.starts_with("?auth&t="));
+
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &HttpRequest {
+            method: "GET".to_string(),
+            url: format!("/auth{querystring}&c={}", success.code),
+            headers: Vec::new(),
+            body: Vec::new(),
+        },
+    );
+    assert!(http_response.upgrade.unwrap());
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: format!(
+                "https://canister_id.icp0.io/auth{querystring}&c={}",
+                success.code
+            ),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::Success));
+
+    let (success, signed) =
+        send_magic_link_via_http_relay(&mut env, sender, canister_id, "b@blah.com");
+    let querystring = signed
+        .sign(&email_sender_rsa_signing_key())
+        .build_querystring();
+
+    env.advance_time(Duration::from_millis(
+        success.magic_link_expiration - success.created + 1,
+    ));
+
+    // Sending another link prunes the short token once the link it was issued for has expired
+    send_magic_link_via_http_relay(&mut env, sender, canister_id, "c@blah.com");
+
+    let response = client::handle_magic_link(
+        &mut env,
+        sender,
+        canister_id,
+        &HandleMagicLinkArgs {
+            link: format!(
+                "https://canister_id.icp0.io/auth{querystring}&c={}",
+                success.code
+            ),
+        },
+    );
+    assert!(matches!(response, HandleMagicLinkResponse::LinkInvalid(_)));
+}
+
+#[test]
+fn auth_pages_are_branded_and_localized() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister_with_args(InitArgs {
+        branding: Some(Branding {
+            app_name: Some("TestApp".to_string()),
+            logo_url: Some("https://example.com/logo.png".to_string()),
+            return_url: Some("https://example.com".to_string()),
+        }),
+        ..default_init_args().to_init_args()
+    });
+
+    let sender = random_principal();
+    let (success, signed) =
+        generate_and_sign_magic_link(&mut env, sender, canister_id, "blah@blah.com");
+
+    let mut http_request = auth_http_request(&signed, &success.code);
+    http_request.headers = vec![(
+        "Accept-Language".to_string(),
+        "fr-CH, fr;q=0.9, en;q=0.8".to_string(),
+    )];
+
+    let http_response = client::http_request_update(&mut env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 200);
+
+    let body = String::from_utf8(http_response.body).unwrap();
+    assert!(body.contains(r#"<html lang="fr">"#));
+    assert!(body.contains("Revenir à TestApp"));
+    assert!(body.contains(r#"href="https://example.com""#));
+    assert!(body.contains(r#"src="https://example.com/logo.png""#));
+
+    // Pages served from the query path are localized too
+    http_request.headers = vec![("Accept-Language".to_string(), "es".to_string())];
+    let http_response = client::http_request(&env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 400);
+
+    let body = String::from_utf8(http_response.body).unwrap();
+    assert!(body.contains("Enlace ya utilizado"));
+    assert!(body.contains("TestApp"));
+}
+
+#[test]
+fn code_can_be_submitted_via_form() {
+    let TestEnv {
+        mut env,
+        canister_id,
+        ..
+    } = client::install_canister();
+
+    let sender = random_principal();
+    let email = "blah@blah.com";
+    let (success, signed) = generate_and_sign_magic_link(&mut env, sender, canister_id, email);
+    let url = format!(
+        "https://canister_id.icp0.io/auth{}",
+        signed.build_querystring()
+    );
+
+    let http_response = client::http_request(
+        &env,
+        sender,
+        canister_id,
+        &HttpRequest {
+            method: "GET".to_string(),
+            url: url.clone(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        },
+    );
+    assert_eq!(http_response.status_code, 200);
+    assert!(http_response.upgrade.is_none());
+    assert!(String::from_utf8(http_response.body)
+        .unwrap()
+        .contains(r#"<form method="post">"#));
+
+    let http_request = HttpRequest {
+        method: "POST".to_string(),
+        url,
+        headers: vec![(
+            "content-type".to_string(),
+            "application/x-www-form-urlencoded".to_string(),
+        )],
+        body: format!("c={}", success.code).into_bytes(),
+    };
+
+    let http_response = client::http_request(&env, sender, canister_id, &http_request);
+    assert!(http_response.upgrade.unwrap());
+
+    let http_response = client::http_request_update(&mut env, sender, canister_id, &http_request);
+    assert_eq!(http_response.status_code, 200);
+
+    let get_delegation_response = client::get_delegation(
+        &env,
+        sender,
+        canister_id,
+        &GetDelegationArgs {
+            email: email.to_string(),
+            session_key: signed.magic_link.delegation().pubkey.clone(),
+            expiration: success.expiration,
+        },
+    );
+    assert!(matches!(
+        get_delegation_response,
+        GetDelegationResponse::Success(_)
+    ));
+}
+
+fn generate_and_sign_magic_link(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    email: &str,
+) -> (GenerateMagicLinkSuccess, DoubleSignedMagicLink) {
+    let identity = create_session_identity();
+    let session_key = identity.public_key().unwrap();
+
+    let response = client::generate_magic_link(
+        env,
+        sender,
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: email.to_string(),
+            session_key: session_key.clone(),
+            max_time_to_live: None,
+            magic_link_time_to_live: None,
+        },
+    );
+
+    let GenerateMagicLinkResponse::Success(success) = response else {
+        panic!("{response:?}");
+    };
+
+    let signed = generate_magic_link(
+        email,
+        session_key,
+        success.created,
+        success.expiration,
+        success.code.clone(),
+        success.magic_link_expiration - success.created,
+    );
+
+    (success, signed)
+}
+
+fn set_http_relay_email_sender_config(
+    env: &mut PocketIc,
+    controller: Principal,
+    canister_id: Principal,
+) {
+    let rsa_public_key = client::rsa_public_key(env, controller, canister_id).unwrap();
+    let rsa_public_key = RsaPublicKey::from_public_key_pem(&rsa_public_key).unwrap();
+    let config = EmailSenderConfig::HttpRelay(HttpRelayEmailSenderConfig {
+        url: "https://relay.blah.com/send".to_string(),
+        hmac_key: "hmac_key".to_string(),
+    });
+    let encrypted = config.encrypt(&rsa_public_key, &mut rand::thread_rng());
+
+    let response = client::set_email_sender_config(env, controller, canister_id, &encrypted);
+    assert!(matches!(response, SetEmailSenderConfigResponse::Success));
+}
+
+// Acts as the relay, returning the magic link which it was sent
+fn send_magic_link_via_http_relay(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    email: &str,
+) -> (GenerateMagicLinkSuccess, SignedMagicLink) {
+    let identity = create_session_identity();
+    let message_id = client::submit_generate_magic_link(
+        env,
+        sender,
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: email.to_string(),
+            session_key: identity.public_key().unwrap(),
+            max_time_to_live: None,
+            magic_link_time_to_live: None,
+        },
+    );
+    env.tick();
+    env.tick();
+
+    let requests = env.get_canister_http();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    let signed: SignedMagicLink = serde_json::from_slice(&request.body).unwrap();
+
+    env.mock_canister_http_response(MockCanisterHttpResponse {
+        subnet_id: request.subnet_id,
+        request_id: request.request_id,
+        response: CanisterHttpResponse::CanisterHttpReply(CanisterHttpReply {
+            status: 200,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }),
+        additional_responses: Vec::new(),
+    });
+
+    let response = client::await_generate_magic_link(env, message_id);
+    let GenerateMagicLinkResponse::Success(success) = response else {
+        panic!("{response:?}");
+    };
+
+    (success, signed)
+}
+
+fn generate_magic_link_signed_by(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    email: &str,
+    email_sender_signing_key: &SigningKey,
+) -> (GenerateMagicLinkSuccess, DoubleSignedMagicLink) {
+    let identity = create_session_identity();
+    let session_key = identity.public_key().unwrap();
+
+    let response = client::generate_magic_link(
+        env,
+        sender,
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: email.to_string(),
+            session_key: session_key.clone(),
+            max_time_to_live: None,
+            magic_link_time_to_live: None,
+        },
+    );
+
+    let GenerateMagicLinkResponse::Success(success) = response else {
+        panic!("{response:?}");
+    };
+
+    let signed = generate_magic_link_with_keys(
+        email,
+        session_key,
+        success.created,
+        success.expiration,
+        success.code.clone(),
+        success.magic_link_expiration - success.created,
+        &rsa_signing_key(),
+        email_sender_signing_key,
+    );
+
+    (success, signed)
+}
+
+fn auth_link(signed: &DoubleSignedMagicLink, code: &str) -> String {
+    format!(
+        "https://canister_id.icp0.io/auth{}&c={code}",
+        signed.build_querystring()
+    )
+}
+
+fn auth_http_request(signed: &DoubleSignedMagicLink, code: &str) -> HttpRequest {
+    HttpRequest {
+        method: "GET".to_string(),
+        url: auth_link(signed, code),
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+fn incorrect_code(code: &str) -> String {
+    let incorrect = (code.parse::<u32>().unwrap() + 1) % 1000;
+    format!("{incorrect:0>3}")
+}
+
+fn signature_map_size(env: &PocketIc, canister_id: Principal) -> usize {
+    let http_response = client::http_request(
+        env,
+        random_principal(),
+        canister_id,
+        &HttpRequest {
+            method: "GET".to_string(),
+            url: "https://canister_id.icp0.io/metrics".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        },
+    );
+
+    String::from_utf8(http_response.body)
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("sign_in_with_email_signature_map_size "))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+fn has_header(http_response: &HttpResponse, name: &str) -> bool {
+    http_response
+        .headers
+        .iter()
+        .any(|(k, _)| k.eq_ignore_ascii_case(name))
+}
+
+fn generate_magic_link_for_email(
+    env: &mut PocketIc,
+    sender: Principal,
+    canister_id: Principal,
+    email: &str,
+) -> GenerateMagicLinkResponse {
+    let identity = create_session_identity();
+
+    client::generate_magic_link(
+        env,
+        sender,
+        canister_id,
+        &GenerateMagicLinkArgs {
+            email: email.to_string(),
+            session_key: identity.public_key().unwrap(),
+            max_time_to_live: None,
+            magic_link_time_to_live: None,
+        },
+    )
+}
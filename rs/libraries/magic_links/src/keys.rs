@@ -0,0 +1,163 @@
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15;
+use rsa::pkcs8::{DecodePublicKey, EncodePublicKey, LineEnding};
+use rsa::sha2::Sha256;
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+
+// The DER encoding of Ed25519 keys is a fixed prefix identifying the algorithm followed by the key
+const ED25519_PUBLIC_KEY_DER_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+const ED25519_PRIVATE_KEY_DER_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+// The keys used to sign magic links, either by the canister or by the email sender
+#[derive(Clone)]
+pub enum SigningKey {
+    Rsa(RsaPrivateKey),
+    Ed25519([u8; 32]),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum PublicKey {
+    Rsa(RsaPublicKey),
+    Ed25519([u8; 32]),
+}
+
+impl SigningKey {
+    // Accepts either a PKCS#1 encoded RSA key or a PKCS#8 encoded Ed25519 key
+    pub fn from_pem(pem: &str) -> Result<SigningKey, String> {
+        if let Ok(rsa_private_key) = RsaPrivateKey::from_pkcs1_pem(pem) {
+            return Ok(SigningKey::Rsa(rsa_private_key));
+        }
+
+        decode_pem(pem, "PRIVATE KEY")
+            .and_then(|der| {
+                der.strip_prefix(&ED25519_PRIVATE_KEY_DER_PREFIX)?
+                    .try_into()
+                    .ok()
+            })
+            .map(SigningKey::Ed25519)
+            .ok_or_else(|| "Private key must be a PKCS#1 RSA key or PKCS#8 Ed25519 key".to_string())
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+        match self {
+            SigningKey::Rsa(rsa_private_key) => {
+                let signing_key: pkcs1v15::SigningKey<Sha256> =
+                    pkcs1v15::SigningKey::new(rsa_private_key.clone());
+                signing_key.sign(msg).to_vec()
+            }
+            SigningKey::Ed25519(bytes) => ed25519_consensus::SigningKey::from(*bytes)
+                .sign(msg)
+                .to_bytes()
+                .to_vec(),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        match self {
+            SigningKey::Rsa(rsa_private_key) => PublicKey::Rsa(rsa_private_key.to_public_key()),
+            SigningKey::Ed25519(bytes) => PublicKey::Ed25519(
+                ed25519_consensus::SigningKey::from(*bytes)
+                    .verification_key()
+                    .to_bytes(),
+            ),
+        }
+    }
+}
+
+impl From<RsaPrivateKey> for SigningKey {
+    fn from(value: RsaPrivateKey) -> Self {
+        SigningKey::Rsa(value)
+    }
+}
+
+impl PublicKey {
+    // Accepts a SubjectPublicKeyInfo encoded RSA or Ed25519 key
+    pub fn from_pem(pem: &str) -> Result<PublicKey, String> {
+        if let Ok(rsa_public_key) = RsaPublicKey::from_public_key_pem(pem) {
+            return Ok(PublicKey::Rsa(rsa_public_key));
+        }
+
+        decode_pem(pem, "PUBLIC KEY")
+            .and_then(|der| {
+                der.strip_prefix(&ED25519_PUBLIC_KEY_DER_PREFIX)?
+                    .try_into()
+                    .ok()
+            })
+            .map(PublicKey::Ed25519)
+            .ok_or_else(|| "Public key must be an RSA or Ed25519 key".to_string())
+    }
+
+    pub fn to_pem(&self) -> String {
+        match self {
+            PublicKey::Rsa(rsa_public_key) => {
+                rsa_public_key.to_public_key_pem(LineEnding::LF).unwrap()
+            }
+            PublicKey::Ed25519(bytes) => {
+                let der = [ED25519_PUBLIC_KEY_DER_PREFIX.as_slice(), bytes].concat();
+                format!(
+                    "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+                    BASE64_STANDARD.encode(der)
+                )
+            }
+        }
+    }
+
+    pub fn verify(&self, msg: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PublicKey::Rsa(rsa_public_key) => {
+                let Ok(signature) = pkcs1v15::Signature::try_from(signature) else {
+                    return false;
+                };
+                let verifying_key: pkcs1v15::VerifyingKey<Sha256> =
+                    pkcs1v15::VerifyingKey::new(rsa_public_key.clone());
+                verifying_key.verify(msg, &signature).is_ok()
+            }
+            PublicKey::Ed25519(bytes) => {
+                let Ok(signature) = <[u8; 64]>::try_from(signature) else {
+                    return false;
+                };
+                ed25519_consensus::VerificationKey::try_from(*bytes)
+                    .and_then(|key| key.verify(&signature.into(), msg))
+                    .is_ok()
+            }
+        }
+    }
+}
+
+fn decode_pem(pem: &str, label: &str) -> Option<Vec<u8>> {
+    let body = pem
+        .trim()
+        .strip_prefix(&format!("-----BEGIN {label}-----"))?
+        .strip_suffix(&format!("-----END {label}-----"))?;
+    let base64: String = body.split_whitespace().collect();
+
+    BASE64_STANDARD.decode(base64).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    #[test]
+    fn ed25519_sign_and_verify() {
+        let mut bytes = [0; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let signing_key = SigningKey::Ed25519(bytes);
+        let public_key = PublicKey::from_pem(&signing_key.public_key().to_pem()).unwrap();
+
+        let signature = signing_key.sign(b"abc");
+
+        assert_eq!(signature.len(), 64);
+        assert!(public_key.verify(b"abc", &signature));
+        assert!(!public_key.verify(b"abd", &signature));
+    }
+}
@@ -1,21 +1,33 @@
-use rsa::pkcs1v15::{SigningKey, VerifyingKey};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
 use rsa::rand_core::CryptoRngCore;
-use rsa::sha2::Sha256;
-use rsa::signature::{SignatureEncoding, Signer, Verifier};
-use rsa::{RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
 use sign_in_with_email_canister::{
-    Delegation, Hash, Milliseconds, Nanoseconds, TimestampMillis,
-    DEFAULT_SESSION_EXPIRATION_PERIOD, MAX_SESSION_EXPIRATION_PERIOD, NANOS_PER_MILLISECOND,
+    Delegation, Hash, Milliseconds, Nanoseconds, TimestampMillis, VerificationCodeAlphabet,
+    VerificationCodeFormat, DEFAULT_SESSION_EXPIRATION_PERIOD, MAX_SESSION_EXPIRATION_PERIOD,
+    NANOS_PER_MILLISECOND,
 };
+use std::fmt::{Display, Formatter};
 use utils::hash_bytes;
 
-const MAGIC_LINK_EXPIRATION: Milliseconds = 10 * 60 * 1000; // 10 minutes
+mod keys;
+
+pub use keys::*;
+
+// Used for links created before the time to live was included in the link
+const LEGACY_MAGIC_LINK_EXPIRATION: Milliseconds = 10 * 60 * 1000; // 10 minutes
+const NUMERIC_ALPHABET: &[u8] = b"0123456789";
+const CROCKFORD_BASE32_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ENVELOPE_VERSION: u8 = 1;
+const SHORT_TOKEN_LENGTH_BYTES: usize = 16;
 
 pub fn generate<R: CryptoRngCore>(
     email: String,
     session_key: Vec<u8>,
     max_time_to_live: Option<Nanoseconds>,
+    magic_link_time_to_live: Milliseconds,
+    code_format: &VerificationCodeFormat,
     rng: &mut R,
     now: TimestampMillis,
 ) -> MagicLink {
@@ -24,7 +36,7 @@ pub fn generate<R: CryptoRngCore>(
         MAX_SESSION_EXPIRATION_PERIOD,
     );
 
-    let code = generate_random_3digit_code(rng);
+    let code = generate_code(code_format, rng);
     let now_nanos = now * NANOS_PER_MILLISECOND;
     let expiration = now_nanos.saturating_add(delta);
     let delegation = Delegation {
@@ -32,12 +44,66 @@ pub fn generate<R: CryptoRngCore>(
         expiration,
     };
 
-    MagicLink::new(email, delegation, code, now)
+    MagicLink::new(email, delegation, code, magic_link_time_to_live, now)
 }
 
-pub fn generate_random_3digit_code<R: CryptoRngCore>(rng: &mut R) -> String {
-    let code = rng.next_u32() % 1000;
-    format!("{:0>3}", code)
+pub fn generate_code<R: CryptoRngCore>(format: &VerificationCodeFormat, rng: &mut R) -> String {
+    let alphabet = alphabet(format.alphabet);
+
+    // `gen_range` uses rejection sampling, so each character is chosen without modulo bias
+    (0..format.length)
+        .map(|_| alphabet[rng.gen_range(0..alphabet.len())] as char)
+        .collect()
+}
+
+pub fn is_valid_code(format: &VerificationCodeFormat, code: &str) -> bool {
+    let alphabet = alphabet(format.alphabet);
+
+    code.len() == format.length as usize && code.bytes().all(|b| alphabet.contains(&b))
+}
+
+// Maps a code entered by a user onto the canonical form of the expected code. The alphabet is
+// inferred from the expected code rather than the current format, so that links sent before the
+// format was changed can still be used. For Crockford base32 this ignores case and hyphens and maps
+// the ambiguous characters 'I', 'L' and 'O'.
+pub fn normalize_code(expected_code: &str, code: &str) -> String {
+    let code = code.trim();
+
+    match code_alphabet(expected_code) {
+        VerificationCodeAlphabet::Numeric => code.to_string(),
+        VerificationCodeAlphabet::CrockfordBase32 => code
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| match c.to_ascii_uppercase() {
+                'I' | 'L' => '1',
+                'O' => '0',
+                c => c,
+            })
+            .collect(),
+    }
+}
+
+// A random ID which the canister maps to the signed magic link, so that the link itself only needs
+// to contain the token and the email sender's signature
+pub fn generate_short_token<R: CryptoRngCore>(rng: &mut R) -> String {
+    let mut bytes = [0; SHORT_TOKEN_LENGTH_BYTES];
+    rng.fill_bytes(&mut bytes);
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn code_alphabet(code: &str) -> VerificationCodeAlphabet {
+    if code.bytes().all(|b| NUMERIC_ALPHABET.contains(&b)) {
+        VerificationCodeAlphabet::Numeric
+    } else {
+        VerificationCodeAlphabet::CrockfordBase32
+    }
+}
+
+fn alphabet(alphabet: VerificationCodeAlphabet) -> &'static [u8] {
+    match alphabet {
+        VerificationCodeAlphabet::Numeric => NUMERIC_ALPHABET,
+        VerificationCodeAlphabet::CrockfordBase32 => CROCKFORD_BASE32_ALPHABET,
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -46,6 +112,9 @@ pub struct MagicLink {
     email: String,
     delegation: Delegation,
     code: String,
+    // Skipped when absent so that links created before this field existed still hash the same
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time_to_live: Option<Milliseconds>,
 }
 
 impl MagicLink {
@@ -53,6 +122,7 @@ impl MagicLink {
         email: String,
         delegation: Delegation,
         code: String,
+        time_to_live: Milliseconds,
         now: TimestampMillis,
     ) -> MagicLink {
         MagicLink {
@@ -60,6 +130,7 @@ impl MagicLink {
             email,
             delegation,
             code,
+            time_to_live: Some(time_to_live),
         }
     }
 
@@ -79,16 +150,21 @@ impl MagicLink {
         &self.code
     }
 
+    pub fn expiration(&self) -> TimestampMillis {
+        self.created
+            .saturating_add(self.time_to_live.unwrap_or(LEGACY_MAGIC_LINK_EXPIRATION))
+    }
+
     pub fn expired(&self, now: TimestampMillis) -> bool {
-        self.created + MAGIC_LINK_EXPIRATION < now
+        self.expiration() < now
     }
 
     pub fn serialize(&self) -> Vec<u8> {
         rmp_serde::to_vec_named(self).unwrap()
     }
 
-    pub fn deserialize(bytes: &[u8]) -> MagicLink {
-        rmp_serde::from_slice(bytes).unwrap()
+    pub fn deserialize(bytes: &[u8]) -> Result<MagicLink, ParseError> {
+        rmp_serde::from_slice(bytes).map_err(|e| ParseError::InvalidMagicLink(e.to_string()))
     }
 
     pub fn hash(&self) -> Hash {
@@ -96,14 +172,17 @@ impl MagicLink {
         hash_bytes(bytes)
     }
 
-    pub fn sign(self, rsa_private_key: RsaPrivateKey) -> SignedMagicLink {
-        let hash = self.hash();
-        let signing_key: SigningKey<Sha256> = SigningKey::new(rsa_private_key);
-        let signature = signing_key.sign(&hash).to_vec();
+    pub fn sign(self, signing_key: &SigningKey) -> SignedMagicLink {
+        let signature = signing_key.sign(&self.hash());
+        self.with_signature(signature)
+    }
 
+    // Used when the signature is produced externally, eg. by the IC's threshold signing API
+    pub fn with_signature(self, signature: Vec<u8>) -> SignedMagicLink {
         SignedMagicLink {
             magic_link: self,
             signature,
+            short_token: None,
         }
     }
 }
@@ -112,17 +191,26 @@ impl MagicLink {
 pub struct SignedMagicLink {
     pub magic_link: MagicLink,
     pub signature: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_token: Option<String>,
 }
 
 impl SignedMagicLink {
-    pub fn sign(self, rsa_private_key: RsaPrivateKey) -> DoubleSignedMagicLink {
-        let signing_key: SigningKey<Sha256> = SigningKey::new(rsa_private_key);
-        let signature2 = signing_key.sign(&self.signature).to_vec();
+    pub fn with_short_token(self, short_token: String) -> SignedMagicLink {
+        SignedMagicLink {
+            short_token: Some(short_token),
+            ..self
+        }
+    }
+
+    pub fn sign(self, signing_key: &SigningKey) -> DoubleSignedMagicLink {
+        let signature2 = signing_key.sign(&self.signature);
 
         DoubleSignedMagicLink {
             magic_link: self.magic_link,
             signature1: self.signature,
             signature2,
+            short_token: self.short_token,
         }
     }
 }
@@ -132,67 +220,158 @@ pub struct DoubleSignedMagicLink {
     pub magic_link: MagicLink,
     pub signature1: Vec<u8>,
     pub signature2: Vec<u8>,
+    // If set, the querystring only contains this token and `signature2`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_token: Option<String>,
 }
 
 impl DoubleSignedMagicLink {
+    // Multiple email sender keys may be accepted while the email sender's key is being rotated
     pub fn verify_sigs(
         &self,
-        rsa_public_key: RsaPublicKey,
-        email_sender_rsa_public_key: RsaPublicKey,
+        public_key: &PublicKey,
+        email_sender_public_keys: &[PublicKey],
     ) -> bool {
-        if !verify_sig(
-            email_sender_rsa_public_key,
-            &self.signature1,
-            &self.signature2,
-        ) {
-            return false;
+        email_sender_public_keys
+            .iter()
+            .any(|k| k.verify(&self.signature1, &self.signature2))
+            && public_key.verify(&self.magic_link.hash(), &self.signature1)
+    }
+
+    pub fn build_querystring(&self) -> String {
+        match &self.short_token {
+            Some(token) => format!(
+                "?auth&t={token}&s={}",
+                BASE64_URL_SAFE_NO_PAD.encode(&self.signature2)
+            ),
+            None => format!(
+                "?auth&l={}",
+                BASE64_URL_SAFE_NO_PAD.encode(self.to_envelope())
+            ),
         }
+    }
+
+    pub fn from_base64(link: &str) -> Result<DoubleSignedMagicLink, ParseError> {
+        let bytes = BASE64_URL_SAFE_NO_PAD
+            .decode(link)
+            .map_err(|_| ParseError::InvalidBase64("l"))?;
 
-        verify_sig(rsa_public_key, &self.magic_link.hash(), &self.signature1)
+        DoubleSignedMagicLink::from_envelope(&bytes)
     }
 
-    pub fn build_querystring(&self) -> String {
-        format!(
-            "?auth&m={}&s1={}&s2={}",
-            hex_to_string(&self.magic_link.serialize()),
-            hex_to_string(&self.signature1),
-            hex_to_string(&self.signature2),
-        )
+    pub fn from_short_token(
+        signed: SignedMagicLink,
+        signature2: &str,
+    ) -> Result<DoubleSignedMagicLink, ParseError> {
+        let signature2 = BASE64_URL_SAFE_NO_PAD
+            .decode(signature2)
+            .map_err(|_| ParseError::InvalidBase64("s"))?;
+
+        Ok(DoubleSignedMagicLink {
+            magic_link: signed.magic_link,
+            signature1: signed.signature,
+            signature2,
+            short_token: signed.short_token,
+        })
     }
 
+    // Links sent before the compact encoding was introduced hex encode each part separately
     pub fn from_hex_strings(
         magic_link: &str,
         signature1: &str,
         signature2: &str,
-    ) -> DoubleSignedMagicLink {
-        DoubleSignedMagicLink {
-            magic_link: MagicLink::deserialize(&string_to_hex(magic_link)),
-            signature1: string_to_hex(signature1),
-            signature2: string_to_hex(signature2),
+    ) -> Result<DoubleSignedMagicLink, ParseError> {
+        Ok(DoubleSignedMagicLink {
+            magic_link: MagicLink::deserialize(&string_to_hex(magic_link, "m")?)?,
+            signature1: string_to_hex(signature1, "s1")?,
+            signature2: string_to_hex(signature2, "s2")?,
+            short_token: None,
+        })
+    }
+
+    // Layout: version (1 byte), then the serialized magic link and signature1 each prefixed by
+    // their length as a big endian u16, followed by signature2 filling the remaining bytes
+    fn to_envelope(&self) -> Vec<u8> {
+        let magic_link = self.magic_link.serialize();
+        let mut bytes = Vec::with_capacity(
+            5 + magic_link.len() + self.signature1.len() + self.signature2.len(),
+        );
+        bytes.push(ENVELOPE_VERSION);
+        for part in [&magic_link, &self.signature1] {
+            let len = u16::try_from(part.len()).expect("Magic link too long");
+            bytes.extend_from_slice(&len.to_be_bytes());
+            bytes.extend_from_slice(part);
         }
+        bytes.extend_from_slice(&self.signature2);
+        bytes
+    }
+
+    fn from_envelope(bytes: &[u8]) -> Result<DoubleSignedMagicLink, ParseError> {
+        let (&version, remaining) = bytes.split_first().ok_or(ParseError::InvalidEnvelope)?;
+        if version != ENVELOPE_VERSION {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+
+        let (magic_link, remaining) = split_length_prefixed(remaining)?;
+        let (signature1, signature2) = split_length_prefixed(remaining)?;
+
+        Ok(DoubleSignedMagicLink {
+            magic_link: MagicLink::deserialize(magic_link)?,
+            signature1: signature1.to_vec(),
+            signature2: signature2.to_vec(),
+            short_token: None,
+        })
     }
 }
 
-fn verify_sig(rsa_public_key: RsaPublicKey, msg: &[u8], signature: &[u8]) -> bool {
-    let Ok(rsa_signature) = rsa::pkcs1v15::Signature::try_from(signature) else {
-        return false;
-    };
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    InvalidHex(&'static str),
+    InvalidBase64(&'static str),
+    InvalidEnvelope,
+    UnsupportedVersion(u8),
+    InvalidMagicLink(String),
+}
 
-    let verifying_key: VerifyingKey<Sha256> = VerifyingKey::new(rsa_public_key);
-    verifying_key.verify(msg, &rsa_signature).is_ok()
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidHex(param) => write!(f, "Parameter '{param}' is not valid hex"),
+            ParseError::InvalidBase64(param) => {
+                write!(f, "Parameter '{param}' is not valid base64")
+            }
+            ParseError::InvalidEnvelope => write!(f, "Magic link envelope malformed"),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "Magic link version {version} not supported")
+            }
+            ParseError::InvalidMagicLink(error) => write!(f, "Magic link malformed: {error}"),
+        }
+    }
 }
 
-fn hex_to_string(bytes: &[u8]) -> String {
-    hex::encode(bytes)
+impl std::error::Error for ParseError {}
+
+fn split_length_prefixed(bytes: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let (len, remaining) = bytes
+        .split_first_chunk::<2>()
+        .ok_or(ParseError::InvalidEnvelope)?;
+    let len = u16::from_be_bytes(*len) as usize;
+
+    if remaining.len() < len {
+        Err(ParseError::InvalidEnvelope)
+    } else {
+        Ok(remaining.split_at(len))
+    }
 }
 
-fn string_to_hex(str: &str) -> Vec<u8> {
-    hex::decode(str).unwrap()
+fn string_to_hex(str: &str, param: &'static str) -> Result<Vec<u8>, ParseError> {
+    hex::decode(str).map_err(|_| ParseError::InvalidHex(param))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn verify_sigs() {
@@ -204,17 +383,190 @@ mod tests {
                 expiration: 1000000000,
             },
             code: "123".to_string(),
+            time_to_live: Some(600000),
         };
 
         let mut rng = rand::thread_rng();
-        let private_key1 = RsaPrivateKey::new(&mut rng, 2048).unwrap();
-        let public_key1 = private_key1.to_public_key();
+        let private_key1 = SigningKey::Rsa(rsa::RsaPrivateKey::new(&mut rng, 2048).unwrap());
+        let private_key2 = SigningKey::Ed25519(rng.gen());
 
-        let private_key2 = RsaPrivateKey::new(&mut rng, 2048).unwrap();
-        let public_key2 = private_key2.to_public_key();
+        let signed = magic_link.sign(&private_key1).sign(&private_key2);
 
-        let signed = magic_link.sign(private_key1).sign(private_key2);
+        let private_key3 = SigningKey::Ed25519(rng.gen());
 
-        assert!(signed.verify_sigs(public_key1, public_key2));
+        assert!(signed.verify_sigs(&private_key1.public_key(), &[private_key2.public_key()]));
+        assert!(signed.verify_sigs(
+            &private_key1.public_key(),
+            &[private_key3.public_key(), private_key2.public_key()]
+        ));
+        assert!(!signed.verify_sigs(&private_key1.public_key(), &[private_key3.public_key()]));
+        assert!(!signed.verify_sigs(&private_key2.public_key(), &[private_key1.public_key()]));
+    }
+
+    #[test]
+    fn legacy_links_roundtrip_without_time_to_live() {
+        let magic_link = MagicLink {
+            created: 1000,
+            email: "a@b.com".to_string(),
+            delegation: Delegation {
+                pubkey: vec![2; 32],
+                expiration: 1000000000,
+            },
+            code: "123".to_string(),
+            time_to_live: None,
+        };
+
+        let bytes = magic_link.serialize();
+        let deserialized = MagicLink::deserialize(&bytes).unwrap();
+
+        assert_eq!(deserialized.serialize(), bytes);
+        assert_eq!(
+            deserialized.expiration(),
+            1000 + LEGACY_MAGIC_LINK_EXPIRATION
+        );
+    }
+
+    #[test]
+    fn generated_codes_match_format() {
+        let mut rng = rand::thread_rng();
+
+        for alphabet in [
+            VerificationCodeAlphabet::Numeric,
+            VerificationCodeAlphabet::CrockfordBase32,
+        ] {
+            let format = VerificationCodeFormat {
+                length: 8,
+                alphabet,
+            };
+
+            for _ in 0..100 {
+                let code = generate_code(&format, &mut rng);
+                assert!(is_valid_code(&format, &code));
+                assert_eq!(normalize_code(&code, &code), code);
+            }
+        }
+    }
+
+    #[test]
+    fn normalize_crockford_base32_code() {
+        assert_eq!(normalize_code("ABCD1100", " abcd-ilo0 "), "ABCD1100");
+    }
+
+    #[test]
+    fn normalize_numeric_code() {
+        assert_eq!(normalize_code("123456", " 123456 "), "123456");
+        assert_eq!(normalize_code("123456", "l23456"), "l23456");
+    }
+
+    #[test]
+    fn short_token_querystring_roundtrips() {
+        let signed = SignedMagicLink {
+            magic_link: MagicLink::new(
+                "a@b.com".to_string(),
+                Delegation {
+                    pubkey: vec![2; 32],
+                    expiration: 1000000000,
+                },
+                "123".to_string(),
+                600000,
+                1000,
+            ),
+            signature: vec![3; 256],
+            short_token: None,
+        };
+        let token = generate_short_token(&mut rand::thread_rng());
+        let double_signed = DoubleSignedMagicLink {
+            magic_link: signed.magic_link.clone(),
+            signature1: signed.signature.clone(),
+            signature2: vec![4; 256],
+            short_token: Some(token.clone()),
+        };
+
+        let querystring = double_signed.build_querystring();
+        let (querystring_token, signature2) = querystring
+            .strip_prefix("?auth&t=")
+            .and_then(|q| q.split_once("&s="))
+            .unwrap();
+        assert_eq!(querystring_token, token);
+
+        let parsed =
+            DoubleSignedMagicLink::from_short_token(signed.with_short_token(token), signature2)
+                .unwrap();
+        assert_eq!(parsed.signature1, vec![3; 256]);
+        assert_eq!(parsed.signature2, vec![4; 256]);
+    }
+
+    #[test]
+    fn unsupported_envelope_version_is_rejected() {
+        let link = BASE64_URL_SAFE_NO_PAD.encode([2, 0, 0, 0, 0]);
+
+        assert_eq!(
+            DoubleSignedMagicLink::from_base64(&link).unwrap_err(),
+            ParseError::UnsupportedVersion(2)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_arbitrary_strings_never_panics(m in ".*", s1 in ".*", s2 in ".*") {
+            let _ = DoubleSignedMagicLink::from_hex_strings(&m, &s1, &s2);
+        }
+
+        #[test]
+        fn parsing_arbitrary_bytes_never_panics(
+            m in prop::collection::vec(any::<u8>(), 0..512),
+            s1 in prop::collection::vec(any::<u8>(), 0..512),
+            s2 in prop::collection::vec(any::<u8>(), 0..512),
+        ) {
+            let result = DoubleSignedMagicLink::from_hex_strings(
+                &hex::encode(m),
+                &hex::encode(&s1),
+                &hex::encode(&s2),
+            );
+            if let Ok(link) = result {
+                prop_assert_eq!(link.signature1, s1);
+                prop_assert_eq!(link.signature2, s2);
+            }
+        }
+
+        #[test]
+        fn parsing_arbitrary_base64_never_panics(
+            bytes in prop::collection::vec(any::<u8>(), 0..1024),
+        ) {
+            let _ = DoubleSignedMagicLink::from_base64(&BASE64_URL_SAFE_NO_PAD.encode(bytes));
+        }
+
+        #[test]
+        fn querystring_roundtrips(
+            created in any::<u64>(),
+            email in "[a-z]{1,20}@[a-z]{1,20}\\.com",
+            pubkey in prop::collection::vec(any::<u8>(), 0..100),
+            expiration in any::<u64>(),
+            code in "[0-9A-Z]{3,16}",
+            time_to_live in any::<Option<u64>>(),
+            signature1 in prop::collection::vec(any::<u8>(), 0..300),
+            signature2 in prop::collection::vec(any::<u8>(), 0..300),
+        ) {
+            let signed = DoubleSignedMagicLink {
+                magic_link: MagicLink {
+                    created,
+                    email,
+                    delegation: Delegation { pubkey, expiration },
+                    code,
+                    time_to_live,
+                },
+                signature1,
+                signature2,
+                short_token: None,
+            };
+
+            let querystring = signed.build_querystring();
+            let link = querystring.strip_prefix("?auth&l=").unwrap();
+
+            let parsed = DoubleSignedMagicLink::from_base64(link).unwrap();
+            prop_assert_eq!(parsed.magic_link.serialize(), signed.magic_link.serialize());
+            prop_assert_eq!(parsed.signature1, signed.signature1);
+            prop_assert_eq!(parsed.signature2, signed.signature2);
+        }
     }
 }
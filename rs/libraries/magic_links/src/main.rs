@@ -1,8 +1,9 @@
 use clap::Parser;
-use magic_links::generate;
+use magic_links::{generate, SigningKey};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use rsa::RsaPrivateKey;
+use sign_in_with_email_canister::{MagicLinkExpiryPolicy, VerificationCodeFormat};
 use utils::ValidatedEmail;
 
 const TEST_SALT: [u8; 32] = [1; 32];
@@ -21,12 +22,14 @@ fn main() {
         email.to_string(),
         session_key,
         None,
+        MagicLinkExpiryPolicy::default().default_time_to_live,
+        &VerificationCodeFormat::default(),
         &mut rng,
         opts.timestamp,
     );
     let signed = magic_link
-        .sign(rsa_private_key)
-        .sign(email_sender_rsa_private_key);
+        .sign(&SigningKey::Rsa(rsa_private_key))
+        .sign(&SigningKey::Rsa(email_sender_rsa_private_key));
 
     let querystring = signed.build_querystring();
 
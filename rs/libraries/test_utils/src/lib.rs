@@ -1,10 +1,12 @@
-use magic_links::{DoubleSignedMagicLink, MagicLink};
+use magic_links::{DoubleSignedMagicLink, MagicLink, SigningKey};
 use rand::rngs::StdRng;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rsa::pkcs1::LineEnding;
 use rsa::pkcs8::EncodePublicKey;
 use rsa::RsaPrivateKey;
-use sign_in_with_email_canister::{Delegation, InitArgs, InitOrUpgradeArgs, TimestampNanos};
+use sign_in_with_email_canister::{
+    Delegation, InitArgs, InitOrUpgradeArgs, Milliseconds, TimestampNanos,
+};
 
 pub const TEST_SALT: [u8; 32] = [1; 32];
 pub const EMAIL_SENDER_RSA_SEED: [u8; 32] = [2; 32];
@@ -14,6 +16,14 @@ pub fn default_init_args() -> InitOrUpgradeArgs {
         email_sender_public_key_pem: email_sender_public_key_pem(),
         whitelisted_principals: vec![],
         salt: Some(TEST_SALT),
+        email_rate_limit_policy: None,
+        throttle_policy: None,
+        verification_code_format: None,
+        max_incorrect_code_attempts: None,
+        magic_link_expiry_policy: None,
+        branding: None,
+        short_magic_links: None,
+        magic_link_signature_scheme: None,
     })
 }
 
@@ -23,17 +33,54 @@ pub fn generate_magic_link(
     created: TimestampNanos,
     expiration: TimestampNanos,
     code: String,
+    time_to_live: Milliseconds,
+) -> DoubleSignedMagicLink {
+    generate_magic_link_with_keys(
+        email,
+        session_key,
+        created,
+        expiration,
+        code,
+        time_to_live,
+        &rsa_signing_key(),
+        &email_sender_rsa_signing_key(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_magic_link_with_keys(
+    email: &str,
+    session_key: Vec<u8>,
+    created: TimestampNanos,
+    expiration: TimestampNanos,
+    code: String,
+    time_to_live: Milliseconds,
+    signing_key: &SigningKey,
+    email_sender_signing_key: &SigningKey,
 ) -> DoubleSignedMagicLink {
     let delegation = Delegation {
         pubkey: session_key,
         expiration,
     };
-    let magic_link = MagicLink::new(email.to_string(), delegation, code, created);
-    let private_key = rsa_private_key();
+    let magic_link = MagicLink::new(email.to_string(), delegation, code, time_to_live, created);
+
+    magic_link.sign(signing_key).sign(email_sender_signing_key)
+}
+
+// Matches the RSA key generated by the canister in test mode
+pub fn rsa_signing_key() -> SigningKey {
+    SigningKey::Rsa(rsa_private_key())
+}
+
+// Matches the key generated by the canister in test mode, which is generated after its RSA key
+pub fn ed25519_signing_key() -> SigningKey {
+    let mut rng = StdRng::from_seed(TEST_SALT);
+    RsaPrivateKey::new(&mut rng, 2048).unwrap();
+    SigningKey::Ed25519(rng.gen())
+}
 
-    magic_link
-        .sign(private_key)
-        .sign(email_sender_rsa_private_key())
+pub fn email_sender_rsa_signing_key() -> SigningKey {
+    SigningKey::Rsa(email_sender_rsa_private_key())
 }
 
 fn rsa_private_key() -> RsaPrivateKey {
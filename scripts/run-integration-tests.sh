@@ -19,8 +19,11 @@ else
     exit 1
 fi
 
+# The HTTP relay email sender isn't enabled by default but is needed by the tests
 echo "Building canister wasm"
-dfx build sign_in_with_email --ic --check
+cargo build --target wasm32-unknown-unknown --release --package sign_in_with_email_canister_impl --features http_relay || exit 1
+mkdir -p .dfx/ic/canisters/sign_in_with_email
+gzip -c target/wasm32-unknown-unknown/release/sign_in_with_email_canister_impl.wasm > .dfx/ic/canisters/sign_in_with_email/sign_in_with_email.wasm.gz
 
 cd rs/integration_tests
 echo "PocketIC download starting"